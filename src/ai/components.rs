@@ -0,0 +1,251 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy_inspector_egui::Inspectable;
+
+use crate::TILE_SIZE;
+
+/// Seconds an enemy stands still after noticing the player before it charges
+const NOTICE_DURATION: f32 = 0.4;
+/// Seconds between two attacks
+const ATTACK_COOLDOWN: f32 = 1.;
+/// Enemies this far from their post in pixels give up the chase
+const LEASH_DISTANCE: f32 = 10. * TILE_SIZE as f32;
+/// Returning enemies are home within this distance to their post
+const HOME_RADIUS: f32 = 8.;
+/// Seconds a patrol waits at every waypoint
+const PATROL_PAUSE: f32 = 1.5;
+/// Seconds a patrol keeps trying to get closer to a waypoint before it skips it
+pub const PATROL_STALL_TIMEOUT: f32 = 2.;
+/// Pixels a patrolling unit has to get closer to its waypoint to count as making progress
+const PATROL_MIN_PROGRESS: f32 = 1.;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Inspectable)]
+pub enum AiState {
+    /// Roams around until the player comes into sight
+    Wander,
+    /// Walks its patrol route until the player comes into sight
+    Patrol,
+    /// Stands still for a moment after spotting the player
+    Notice,
+    /// Follows the player's distance map
+    Chase,
+    /// Stands next to the player and hits them
+    Attack,
+    /// Lost interest and walks back to its post
+    Return,
+}
+
+/// Sent whenever an enemy switches its state, the state itself only changes by these
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AiStateChanged {
+    pub entity: Entity,
+    pub from: AiState,
+    pub to: AiState,
+}
+
+/// Everything an enemy takes into account to change its state
+pub struct Perception {
+    /// The player is within the aggro radius and in line of sight
+    pub sees_player: bool,
+    /// Walking distance to the player in tiles if the player can be reached
+    pub walking_distance: Option<i32>,
+    /// Distance to the feet of the player
+    pub player_distance: f32,
+    pub distance_from_post: f32,
+}
+
+#[derive(Component, Inspectable)]
+pub struct Behaviour {
+    pub state: AiState,
+    /// The state the enemy is in while it doesn't care about the player
+    pub idle: AiState,
+    /// Where the enemy returns to after losing interest
+    pub post: Vec3,
+    /// Distance in pixels within which the enemy notices the player in sight
+    pub aggro_radius: f32,
+    /// Walking distance in tiles up to which the enemy follows the player around corners
+    pub chase_range: i32,
+    /// Distance to the player in pixels the enemy can hit from
+    pub attack_range: f32,
+    pub damage: u32,
+    /// Time spent in the current state
+    #[inspectable(ignore)]
+    pub state_timer: Timer,
+    #[inspectable(ignore)]
+    pub attack_cooldown: Timer,
+}
+
+impl Behaviour {
+    pub fn new(post: Vec3, damage: u32) -> Self {
+        Self {
+            state: AiState::Wander,
+            idle: AiState::Wander,
+            post,
+            aggro_radius: 6. * TILE_SIZE as f32,
+            chase_range: 8,
+            attack_range: 20.,
+            damage,
+            state_timer: Timer::from_seconds(NOTICE_DURATION, false),
+            attack_cooldown: Timer::from_seconds(ATTACK_COOLDOWN, false),
+        }
+    }
+
+    /// Enemies with a [`Patrol`] walk it instead of wandering around
+    pub fn patrolling(self) -> Self {
+        Self {
+            state: AiState::Patrol,
+            idle: AiState::Patrol,
+            ..self
+        }
+    }
+
+    /// The state the enemy should switch to, if any
+    pub fn next_state(&self, perception: &Perception) -> Option<AiState> {
+        match self.state {
+            AiState::Wander | AiState::Patrol => perception.sees_player.then_some(AiState::Notice),
+            AiState::Notice if !perception.sees_player => Some(self.idle),
+            AiState::Notice => self.state_timer.finished().then_some(AiState::Chase),
+            AiState::Chase | AiState::Attack if self.lost_interest(perception) => {
+                Some(AiState::Return)
+            }
+            AiState::Chase => {
+                (perception.player_distance <= self.attack_range).then_some(AiState::Attack)
+            }
+            AiState::Attack => {
+                (perception.player_distance > self.attack_range).then_some(AiState::Chase)
+            }
+            AiState::Return => (perception.distance_from_post <= HOME_RADIUS).then_some(self.idle),
+        }
+    }
+
+    fn lost_interest(&self, perception: &Perception) -> bool {
+        let in_range = matches!(perception.walking_distance, Some(d) if d <= self.chase_range);
+        !in_range || perception.distance_from_post > LEASH_DISTANCE
+    }
+}
+
+/// A loop of waypoints an enemy walks along, waiting a moment at each of them
+#[derive(Component, Inspectable)]
+pub struct Patrol {
+    pub waypoints: Vec<Vec3>,
+    next: usize,
+    #[inspectable(ignore)]
+    pause: Timer,
+    #[inspectable(ignore)]
+    stall: Timer,
+    /// The closest the unit got to the waypoint it is heading to
+    #[inspectable(ignore)]
+    closest: f32,
+}
+
+impl Patrol {
+    /// Routes need at least one waypoint
+    pub fn new(waypoints: Vec<Vec3>) -> Option<Self> {
+        if waypoints.is_empty() {
+            return None;
+        }
+        let mut pause = Timer::from_seconds(PATROL_PAUSE, false);
+        pause.tick(pause.duration());
+        Some(Self {
+            waypoints,
+            next: 0,
+            pause,
+            stall: Timer::from_seconds(PATROL_STALL_TIMEOUT, false),
+            closest: f32::INFINITY,
+        })
+    }
+
+    /// The waypoint the patrol is heading to
+    pub fn target(&self) -> Vec3 {
+        self.waypoints[self.next]
+    }
+
+    pub fn is_paused(&self) -> bool {
+        !self.pause.finished()
+    }
+
+    pub fn tick(&mut self, delta: Duration) {
+        self.pause.tick(delta);
+    }
+
+    /// Tracks the distance to the waypoint and tells whether the unit stopped getting closer to
+    /// it for a while, for example because an item or another enemy stands on it
+    pub fn is_stalled(&mut self, distance: f32, delta: Duration) -> bool {
+        if distance < self.closest - PATROL_MIN_PROGRESS {
+            self.closest = distance;
+            self.stall.reset();
+        } else {
+            self.stall.tick(delta);
+        }
+        self.stall.finished()
+    }
+
+    /// Waits at the reached waypoint and heads for the next one afterwards
+    pub fn reach_target(&mut self) {
+        self.next = (self.next + 1) % self.waypoints.len();
+        self.pause.reset();
+        self.stall.reset();
+        self.closest = f32::INFINITY;
+    }
+}
+
+#[test]
+fn should_go_through_states_from_noticing_to_returning() {
+    let mut behaviour = Behaviour::new(Vec3::ZERO, 1);
+    let mut perception = Perception {
+        sees_player: true,
+        walking_distance: Some(3),
+        player_distance: 80.,
+        distance_from_post: 0.,
+    };
+    let step = |behaviour: &mut Behaviour, perception: &Perception| {
+        let next = behaviour.next_state(perception);
+        if let Some(state) = next {
+            behaviour.state = state;
+            behaviour.state_timer.reset();
+        }
+        next
+    };
+
+    assert_eq!(step(&mut behaviour, &perception), Some(AiState::Notice));
+    assert_eq!(step(&mut behaviour, &perception), None);
+    behaviour.state_timer.tick(behaviour.state_timer.duration());
+    assert_eq!(step(&mut behaviour, &perception), Some(AiState::Chase));
+
+    perception.player_distance = 10.;
+    assert_eq!(step(&mut behaviour, &perception), Some(AiState::Attack));
+
+    // Running out of range makes the enemy lose interest even without seeing the player
+    perception.sees_player = false;
+    perception.walking_distance = Some(20);
+    perception.distance_from_post = 100.;
+    assert_eq!(step(&mut behaviour, &perception), Some(AiState::Return));
+    assert_eq!(step(&mut behaviour, &perception), None);
+    perception.distance_from_post = 2.;
+    assert_eq!(step(&mut behaviour, &perception), Some(AiState::Wander));
+}
+
+#[test]
+fn should_loop_patrol_and_go_back_to_it_after_losing_sight() {
+    let waypoints = vec![Vec3::ZERO, Vec3::X, Vec3::Y];
+    assert!(Patrol::new(Vec::new()).is_none());
+    let mut patrol = Patrol::new(waypoints.clone()).unwrap();
+    assert!(!patrol.is_paused());
+    for waypoint in waypoints.iter().chain(waypoints.iter()) {
+        assert_eq!(patrol.target(), *waypoint);
+        patrol.reach_target();
+        assert!(patrol.is_paused());
+        patrol.tick(Duration::from_secs_f32(PATROL_PAUSE));
+    }
+
+    let mut behaviour = Behaviour::new(Vec3::ZERO, 1).patrolling();
+    behaviour.state = AiState::Notice;
+    let perception = Perception {
+        sees_player: false,
+        walking_distance: None,
+        player_distance: 200.,
+        distance_from_post: 0.,
+    };
+    assert_eq!(behaviour.next_state(&perception), Some(AiState::Patrol));
+}
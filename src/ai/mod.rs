@@ -0,0 +1,36 @@
+pub mod components;
+mod systems;
+
+use bevy::prelude::*;
+
+use crate::{
+    movement::components::{IntegrateMovement, StopRejectedMoves},
+    simulation::{SimulationAppExt, SimulationStage},
+};
+
+use self::{
+    components::AiStateChanged,
+    systems::{
+        attack, change_state, chase, patrol, perceive, return_to_post, turn_on_bump, wander,
+    },
+};
+
+/// Enemies decide on their state first and then act on it before their movement is integrated
+pub struct AiPlugin;
+
+impl Plugin for AiPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_simulation_event::<AiStateChanged>()
+            .add_simulation_system(perceive.before(change_state))
+            .add_simulation_system(change_state.before(IntegrateMovement))
+            .add_simulation_system(wander.after(change_state).before(IntegrateMovement))
+            .add_simulation_system(patrol.after(change_state).before(IntegrateMovement))
+            .add_simulation_system(chase.after(change_state).before(IntegrateMovement))
+            .add_simulation_system(attack.after(change_state).before(IntegrateMovement))
+            .add_simulation_system(return_to_post.after(change_state).before(IntegrateMovement))
+            .add_simulation_system_to_stage(
+                SimulationStage::PostUpdate,
+                turn_on_bump.after(StopRejectedMoves),
+            );
+    }
+}
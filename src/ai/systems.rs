@@ -0,0 +1,284 @@
+use bevy::prelude::*;
+
+use super::components::{AiState, AiStateChanged, Behaviour, Patrol, Perception};
+use crate::{
+    collision::components::{CollisionLayer, CollisionLayers, MovementColliders, SpatialHash},
+    combat::components::{Health, Hit},
+    map::components::{get_coordinate_from_index, map_idx_f32, Map, PlayerDistanceMap},
+    movement::{
+        components::{MoveIntent, Velocity, Wander},
+        steering::{self, arrive, seek, Obstacles},
+    },
+    player::components::Player,
+    simulation::{simulation_step, SimulationRng, SIMULATION_STEP},
+    TILE_SIZE,
+};
+
+const FLEE_HEALTH_PERCENTAGE: f32 = 0.3;
+/// How hard a hit of an enemy pushes the player away
+const HIT_KNOCKBACK: f32 = 250.;
+/// Wandering units stroll at this part of their max speed
+const WANDER_SPEED: f32 = 0.6;
+/// Units slow down within this distance to where they are heading instead of overshooting
+const ARRIVE_RADIUS: f32 = 24.;
+/// Patrols count a waypoint as reached within this distance
+const WAYPOINT_RADIUS: f32 = 8.;
+
+/// Looks around for the player and sends the state changes that follow from it
+#[allow(clippy::too_many_arguments)]
+pub fn perceive(
+    map: Res<Map>,
+    player_distance_map: Res<PlayerDistanceMap>,
+    spatial_hash: Res<SpatialHash>,
+    movement_colliders: MovementColliders,
+    parents: Query<&Parent>,
+    player_query: Query<(Entity, &Transform), With<Player>>,
+    mut state_changes: EventWriter<AiStateChanged>,
+    mut query: Query<(Entity, &Transform, &mut Behaviour)>,
+) {
+    let (player, player_pos) = match player_query.get_single() {
+        Ok((player, player_transform)) => (player, player_transform.translation),
+        Err(_) => return,
+    };
+    let player_feet = movement_colliders.hitbox_at(player, player_pos);
+    let is_player = |collider: Entity| matches!(parents.get(collider), Ok(p) if p.0 == player);
+
+    for (entity, transform, mut behaviour) in query.iter_mut() {
+        behaviour.state_timer.tick(simulation_step());
+        behaviour.attack_cooldown.tick(simulation_step());
+
+        let position = transform.translation;
+        let idx = map_idx_f32(position.x, position.y);
+        let sees_player = spatial_hash
+            .within_radius(
+                position,
+                behaviour.aggro_radius,
+                &CollisionLayers::detecting(&[CollisionLayer::Player]),
+            )
+            .iter()
+            .any(|(collider, _)| is_player(*collider))
+            && map.can_see(position, player_pos);
+        let player_distance = match &player_feet {
+            Some(feet) => feet.distance_to(position.truncate()),
+            None => position.distance(player_pos),
+        };
+        let perception = Perception {
+            sees_player,
+            walking_distance: player_distance_map.distances.distance(idx),
+            player_distance,
+            distance_from_post: position.distance(behaviour.post),
+        };
+
+        if let Some(to) = behaviour.next_state(&perception) {
+            state_changes.send(AiStateChanged {
+                entity,
+                from: behaviour.state,
+                to,
+            });
+        }
+    }
+}
+
+pub fn change_state(
+    mut state_changes: EventReader<AiStateChanged>,
+    mut query: Query<&mut Behaviour>,
+) {
+    for state_change in state_changes.iter() {
+        if let Ok(mut behaviour) = query.get_mut(state_change.entity) {
+            behaviour.state = state_change.to;
+            behaviour.state_timer.reset();
+        }
+    }
+}
+
+pub fn wander(
+    mut rng: ResMut<SimulationRng>,
+    obstacles: Obstacles,
+    mut query: Query<(Entity, &Transform, &Behaviour, &mut Wander, &mut MoveIntent)>,
+) {
+    for (entity, transform, behaviour, mut wander, mut intent) in query.iter_mut() {
+        match behaviour.state {
+            AiState::Wander => {}
+            AiState::Notice => {
+                intent.0 = Vec2::ZERO;
+                continue;
+            }
+            _ => continue,
+        }
+        let direction = steering::wander(&mut wander, SIMULATION_STEP as f32, &mut rng.0);
+        intent.0 = obstacles.steer(entity, transform.translation, direction * WANDER_SPEED);
+        // Keep going where the steering led instead of heading back into the obstacle
+        if intent.0 != Vec2::ZERO {
+            wander.angle = intent.y.atan2(intent.x);
+        }
+    }
+}
+
+/// Wandering units turn around as soon as they bump into something that took away their speed in
+/// the direction they are walking
+pub fn turn_on_bump(mut query: Query<(&Behaviour, &mut Wander, &Velocity)>) {
+    for (behaviour, mut wander, velocity) in query.iter_mut() {
+        if behaviour.state == AiState::Wander && velocity.dot(wander.direction()) == 0. {
+            wander.angle += std::f32::consts::PI;
+        }
+    }
+}
+
+/// Follows the player's distance map. Badly hurt units walk uphill instead to flee from the
+/// player.
+pub fn chase(
+    player_distance_map: Res<PlayerDistanceMap>,
+    player_query: Query<&Transform, With<Player>>,
+    obstacles: Obstacles,
+    mut query: Query<(
+        Entity,
+        &Transform,
+        &Behaviour,
+        &mut MoveIntent,
+        Option<&Health>,
+    )>,
+) {
+    let player_pos = match player_query.get_single() {
+        Ok(player_transform) => player_transform.translation.truncate(),
+        Err(_) => return,
+    };
+    for (entity, transform, behaviour, mut intent, health) in query.iter_mut() {
+        if behaviour.state != AiState::Chase {
+            continue;
+        }
+        let idx = map_idx_f32(transform.translation.x, transform.translation.y);
+        let fleeing = matches!(health, Some(h) if h.damage_percentage() < FLEE_HEALTH_PERCENTAGE);
+        let next_idx = if fleeing {
+            player_distance_map.distances.uphill(idx)
+        } else {
+            player_distance_map.distances.downhill(idx)
+        };
+        // Heading for the center of the next tile cuts corners smoothly
+        let position = transform.translation.truncate();
+        let desired = if !fleeing && position.distance(player_pos) < TILE_SIZE as f32 {
+            arrive(position, player_pos, ARRIVE_RADIUS)
+        } else {
+            match next_idx {
+                Some(next_idx) => {
+                    let (x, y) = get_coordinate_from_index(next_idx);
+                    seek(position, Vec2::new(x as f32, y as f32) * TILE_SIZE as f32)
+                }
+                None => Vec2::ZERO,
+            }
+        };
+        intent.0 = obstacles.steer(entity, transform.translation, desired);
+    }
+}
+
+/// Attacking enemies stand still and hit the player whenever their attack cooled down
+pub fn attack(
+    mut hits: EventWriter<Hit>,
+    player_query: Query<Entity, With<Player>>,
+    mut query: Query<(&Transform, &mut Behaviour, &mut MoveIntent)>,
+) {
+    for (transform, mut behaviour, mut intent) in query.iter_mut() {
+        if behaviour.state != AiState::Attack {
+            continue;
+        }
+        intent.0 = Vec2::ZERO;
+        if behaviour.attack_cooldown.finished() {
+            behaviour.attack_cooldown.reset();
+            if let Ok(player) = player_query.get_single() {
+                hits.send(Hit {
+                    target: player,
+                    from: transform.translation,
+                    damage: behaviour.damage,
+                    knockback: HIT_KNOCKBACK,
+                });
+            }
+        }
+    }
+}
+
+pub fn return_to_post(
+    obstacles: Obstacles,
+    mut query: Query<(Entity, &Transform, &Behaviour, &mut MoveIntent)>,
+) {
+    for (entity, transform, behaviour, mut intent) in query.iter_mut() {
+        if behaviour.state != AiState::Return {
+            continue;
+        }
+        let desired = arrive(
+            transform.translation.truncate(),
+            behaviour.post.truncate(),
+            ARRIVE_RADIUS,
+        );
+        intent.0 = obstacles.steer(entity, transform.translation, desired);
+    }
+}
+
+/// Walks the patrol route. The waypoint a patrol heads for is its post, so it comes back there
+/// after losing interest in the player.
+pub fn patrol(
+    obstacles: Obstacles,
+    mut query: Query<(
+        Entity,
+        &Transform,
+        &mut Behaviour,
+        &mut Patrol,
+        &mut MoveIntent,
+    )>,
+) {
+    for (entity, transform, mut behaviour, mut patrol, mut intent) in query.iter_mut() {
+        if behaviour.state != AiState::Patrol {
+            continue;
+        }
+        patrol.tick(simulation_step());
+        if patrol.is_paused() {
+            intent.0 = Vec2::ZERO;
+            continue;
+        }
+        let position = transform.translation.truncate();
+        let distance = position.distance(patrol.target().truncate());
+        // A waypoint blocked by something the room spawned on it is skipped instead of stalling
+        // the patrol forever
+        if distance <= WAYPOINT_RADIUS || patrol.is_stalled(distance, simulation_step()) {
+            patrol.reach_target();
+            intent.0 = Vec2::ZERO;
+            continue;
+        }
+        behaviour.post = patrol.target();
+        let desired = arrive(position, patrol.target().truncate(), ARRIVE_RADIUS);
+        intent.0 = obstacles.steer(entity, transform.translation, desired);
+    }
+}
+
+#[test]
+fn should_skip_waypoint_the_patrol_cannot_get_closer_to() {
+    use super::components::PATROL_STALL_TIMEOUT;
+    use crate::map::components::{map_with_floor, Rectangle};
+
+    let mut world = World::new();
+    world.insert_resource(map_with_floor(&Rectangle::new(2, 2, 8, 8)));
+    world.init_resource::<SpatialHash>();
+    let waypoints = vec![Vec3::new(200., 100., 0.), Vec3::new(100., 200., 0.)];
+    // Nothing moves the unit, just like something standing on the waypoint would stop it
+    let unit = world
+        .spawn()
+        .insert(Transform::from_xyz(100., 100., 0.))
+        .insert(Behaviour::new(Vec3::ZERO, 1).patrolling())
+        .insert(Patrol::new(waypoints.clone()).unwrap())
+        .insert(MoveIntent::default())
+        .id();
+    let mut stage = SystemStage::single_threaded().with_system(patrol);
+
+    let timeout_ticks = (PATROL_STALL_TIMEOUT as f64 / SIMULATION_STEP).round() as usize;
+    let mut ticks = 0;
+    loop {
+        stage.run(&mut world);
+        ticks += 1;
+        if world.get::<Patrol>(unit).unwrap().target() != waypoints[0] {
+            break;
+        }
+        // Still heading for the blocked waypoint
+        assert_ne!(world.get::<MoveIntent>(unit).unwrap().0, Vec2::ZERO);
+        assert!(ticks <= timeout_ticks + 2);
+    }
+    assert!(ticks > timeout_ticks);
+    assert_eq!(world.get::<Patrol>(unit).unwrap().target(), waypoints[1]);
+}
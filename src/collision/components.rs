@@ -1,6 +1,12 @@
+use std::collections::{BTreeSet, HashMap};
+
+use bevy::ecs::system::SystemParam;
 use bevy::prelude::*;
 use bevy::sprite::collide_aabb::collide;
 
+use crate::map::components::tile_span;
+
+/// The world space rectangle of a collider, kept up to date from the unit it belongs to
 #[derive(Component, Clone, PartialEq, Debug)]
 pub struct Hitbox {
     pub pos: Vec3,
@@ -16,4 +22,365 @@ impl Hitbox {
     pub fn collides_with(&self, other_hitbox: &Hitbox) -> bool {
         collide(self.pos, self.size(), other_hitbox.pos, other_hitbox.size()).is_some()
     }
+
+    /// Distance from the point to the closest point of the hitbox, zero if the point lies inside
+    pub fn distance_to(&self, point: Vec2) -> f32 {
+        let half_size = self.size() / 2.;
+        let center = self.pos.truncate();
+        point.distance(point.clamp(center - half_size, center + half_size))
+    }
+
+    /// How far the hitboxes reach into each other on both axes, negative on an axis with a gap
+    fn penetration(&self, other: &Hitbox) -> Vec2 {
+        let distance = (self.pos - other.pos).truncate().abs();
+        (self.size() + other.size()) / 2. - distance
+    }
+
+    pub fn overlap_area(&self, other: &Hitbox) -> f32 {
+        let penetration = self.penetration(other).max(Vec2::ZERO);
+        penetration.x * penetration.y
+    }
+
+    /// The shortest move that takes this hitbox out of the other one, along the axis they
+    /// overlap least. Hitboxes sharing a center are pushed to the right or up.
+    pub fn separation(&self, other: &Hitbox) -> Vec2 {
+        let penetration = self.penetration(other);
+        if penetration.x <= 0. || penetration.y <= 0. {
+            return Vec2::ZERO;
+        }
+        let direction = (self.pos - other.pos).truncate();
+        let sign = |d: f32| if d < 0. { -1. } else { 1. };
+        if penetration.x <= penetration.y {
+            Vec2::new(penetration.x * sign(direction.x), 0.)
+        } else {
+            Vec2::new(0., penetration.y * sign(direction.y))
+        }
+    }
+}
+
+/// A collision shape relative to the unit it is a child of. Colliders without a size cover the
+/// bounds of the unit's sprite.
+#[derive(Component, Clone, Debug)]
+pub struct Collider {
+    pub offset: Vec2,
+    pub size: Option<Vec2>,
+}
+
+impl Collider {
+    pub fn sprite_bounds() -> Self {
+        Self {
+            offset: Vec2::ZERO,
+            size: None,
+        }
+    }
+
+    /// Where the collider is for a unit at the given position
+    pub fn hitbox(&self, unit_pos: Vec3, sprite_bounds: Vec2) -> Hitbox {
+        let size = self.size.unwrap_or(sprite_bounds);
+        Hitbox {
+            pos: unit_pos + self.offset.extend(0.),
+            width: size.x,
+            height: size.y,
+        }
+    }
+}
+
+/// The collider a unit walks with, it is checked against walls and blocks other units. Any other
+/// collider of the unit only takes part in queries and sensors.
+#[derive(Component)]
+pub struct MovementCollider;
+
+/// The components of a collider child of a unit at the given position
+pub fn collider_bundle(
+    collider: Collider,
+    layers: CollisionLayers,
+    unit_pos: Vec3,
+    sprite_bounds: Vec2,
+) -> (Collider, Hitbox, CollisionLayers) {
+    let hitbox = collider.hitbox(unit_pos, sprite_bounds);
+    (collider, hitbox, layers)
+}
+
+/// Looks up the movement collider among the children of a unit
+#[derive(SystemParam)]
+pub struct MovementColliders<'w, 's> {
+    children: Query<'w, 's, &'static Children>,
+    colliders: Query<'w, 's, (&'static Collider, &'static Hitbox), With<MovementCollider>>,
+}
+
+impl<'w, 's> MovementColliders<'w, 's> {
+    pub fn entity(&self, unit: Entity) -> Option<Entity> {
+        let children = self.children.get(unit).ok()?;
+        children
+            .iter()
+            .copied()
+            .find(|child| self.colliders.get(*child).is_ok())
+    }
+
+    /// The movement collider of the unit as if the unit stood at the given position
+    pub fn hitbox_at(&self, unit: Entity, unit_pos: Vec3) -> Option<Hitbox> {
+        let (collider, hitbox) = self.colliders.get(self.entity(unit)?).ok()?;
+        Some(collider.hitbox(unit_pos, Vec2::new(hitbox.width, hitbox.height)))
+    }
+
+    pub fn contains(&self, collider: Entity) -> bool {
+        self.colliders.get(collider).is_ok()
+    }
+}
+
+/// Sensor hitboxes never block movement but report which hitboxes they detect entering and
+/// leaving them
+#[derive(Component)]
+pub struct Sensor;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CollisionStarted {
+    pub sensor: Entity,
+    pub other: Entity,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CollisionEnded {
+    pub sensor: Entity,
+    pub other: Entity,
+}
+
+/// Pairs of sensors and the entities currently overlapping them. Being ordered keeps the events
+/// of a tick in the same order every run.
+#[derive(Default)]
+pub struct SensorContacts(pub BTreeSet<(Entity, Entity)>);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CollisionLayer {
+    Player,
+    Enemy,
+    Item,
+}
+
+impl CollisionLayer {
+    fn bits(layers: &[CollisionLayer]) -> u32 {
+        layers
+            .iter()
+            .fold(0, |bits, layer| bits | 1 << *layer as u32)
+    }
+}
+
+/// The layers a hitbox is part of and the layers it detects. Two hitboxes only block each other
+/// if both detect the other one. Hitboxes without this component are part of and detect every
+/// layer.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CollisionLayers {
+    memberships: u32,
+    mask: u32,
+}
+
+impl CollisionLayers {
+    pub const ALL: Self = Self {
+        memberships: u32::MAX,
+        mask: u32::MAX,
+    };
+
+    pub fn new(memberships: &[CollisionLayer], mask: &[CollisionLayer]) -> Self {
+        Self {
+            memberships: CollisionLayer::bits(memberships),
+            mask: CollisionLayer::bits(mask),
+        }
+    }
+
+    /// Layers for queries that look for hitboxes on the given layers without being part of any
+    pub fn detecting(mask: &[CollisionLayer]) -> Self {
+        Self::new(&[], mask)
+    }
+
+    pub fn detects(&self, other: &CollisionLayers) -> bool {
+        self.mask & other.memberships != 0
+    }
+
+    pub fn blocks(&self, other: &CollisionLayers) -> bool {
+        self.detects(other) && other.detects(self)
+    }
+}
+
+/// Broadphase for hitbox queries. Every hitbox is registered in all tile cells it overlaps, so a
+/// query only has to look at the hitboxes in the cells it touches.
+#[derive(Default)]
+pub struct SpatialHash {
+    cells: HashMap<(i32, i32), Vec<Entity>>,
+    hitboxes: HashMap<Entity, (Hitbox, CollisionLayers)>,
+}
+
+impl SpatialHash {
+    /// Adds the hitbox or moves it if the entity is already known
+    pub fn insert(&mut self, entity: Entity, hitbox: Hitbox, layers: CollisionLayers) {
+        self.remove(entity);
+        for cell in cells(&hitbox) {
+            self.cells.entry(cell).or_default().push(entity);
+        }
+        self.hitboxes.insert(entity, (hitbox, layers));
+    }
+
+    pub fn remove(&mut self, entity: Entity) {
+        if let Some((hitbox, _)) = self.hitboxes.remove(&entity) {
+            for cell in cells(&hitbox) {
+                if let Some(entities) = self.cells.get_mut(&cell) {
+                    entities.retain(|e| *e != entity);
+                }
+            }
+        }
+    }
+
+    /// All hitboxes the layers detect that overlap the given rectangle, ordered by entity
+    pub fn overlapping(&self, rect: &Hitbox, layers: &CollisionLayers) -> Vec<(Entity, &Hitbox)> {
+        self.candidates(rect, *layers)
+            .filter(|(_, hitbox)| hitbox.collides_with(rect))
+            .collect()
+    }
+
+    /// All hitboxes the layers detect that reach into the circle around the center, ordered by
+    /// entity
+    pub fn within_radius(
+        &self,
+        center: Vec3,
+        radius: f32,
+        layers: &CollisionLayers,
+    ) -> Vec<(Entity, &Hitbox)> {
+        let bounds = Hitbox {
+            pos: center,
+            width: radius * 2.,
+            height: radius * 2.,
+        };
+        self.candidates(&bounds, *layers)
+            .filter(|(_, hitbox)| hitbox.distance_to(center.truncate()) <= radius)
+            .collect()
+    }
+
+    /// Detected hitboxes sharing a cell with the rectangle. Sorting them keeps queries independent
+    /// of the order the hash map stores them in.
+    fn candidates(
+        &self,
+        rect: &Hitbox,
+        layers: CollisionLayers,
+    ) -> impl Iterator<Item = (Entity, &Hitbox)> {
+        let mut entities: Vec<Entity> = cells(rect)
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+            .copied()
+            .collect();
+        entities.sort();
+        entities.dedup();
+        entities
+            .into_iter()
+            .map(|entity| (entity, &self.hitboxes[&entity]))
+            .filter(move |(_, (_, other_layers))| layers.detects(other_layers))
+            .map(|(entity, (hitbox, _))| (entity, hitbox))
+    }
+}
+
+fn cells(hitbox: &Hitbox) -> impl Iterator<Item = (i32, i32)> {
+    let (min, max) = tile_span(hitbox);
+    (min.1..=max.1).flat_map(move |y| (min.0..=max.0).map(move |x| (x, y)))
+}
+
+#[test]
+fn should_find_hitboxes_by_rect_and_radius() {
+    let mut spatial_hash = SpatialHash::default();
+    let square = |x: f32, y: f32| Hitbox {
+        pos: Vec3::new(x, y, 0.),
+        width: 20.,
+        height: 20.,
+    };
+    spatial_hash.insert(Entity::from_raw(0), square(0., 0.), CollisionLayers::ALL);
+    spatial_hash.insert(Entity::from_raw(1), square(100., 0.), CollisionLayers::ALL);
+    spatial_hash.insert(
+        Entity::from_raw(2),
+        square(300., 300.),
+        CollisionLayers::ALL,
+    );
+
+    let overlapping: Vec<Entity> = spatial_hash
+        .overlapping(&square(90., 5.), &CollisionLayers::ALL)
+        .into_iter()
+        .map(|(entity, _)| entity)
+        .collect();
+    assert_eq!(overlapping, vec![Entity::from_raw(1)]);
+
+    let within_radius: Vec<Entity> = spatial_hash
+        .within_radius(Vec3::new(50., 0., 0.), 45., &CollisionLayers::ALL)
+        .into_iter()
+        .map(|(entity, _)| entity)
+        .collect();
+    assert_eq!(
+        within_radius,
+        vec![Entity::from_raw(0), Entity::from_raw(1)]
+    );
+}
+
+#[test]
+fn should_forget_old_cells_of_moved_hitboxes() {
+    let mut spatial_hash = SpatialHash::default();
+    let entity = Entity::from_raw(0);
+    let mut hitbox = Hitbox {
+        pos: Vec3::ZERO,
+        width: 20.,
+        height: 20.,
+    };
+    spatial_hash.insert(entity, hitbox.clone(), CollisionLayers::ALL);
+    hitbox.pos = Vec3::new(200., 0., 0.);
+    spatial_hash.insert(entity, hitbox, CollisionLayers::ALL);
+
+    assert!(spatial_hash
+        .within_radius(Vec3::ZERO, 15., &CollisionLayers::ALL)
+        .is_empty());
+    assert_eq!(
+        spatial_hash
+            .within_radius(Vec3::new(200., 0., 0.), 15., &CollisionLayers::ALL)
+            .len(),
+        1
+    );
+    spatial_hash.remove(entity);
+    assert!(spatial_hash
+        .within_radius(Vec3::new(200., 0., 0.), 15., &CollisionLayers::ALL)
+        .is_empty());
+}
+
+#[test]
+fn should_separate_along_axis_of_least_overlap() {
+    let hitbox = |x: f32, y: f32| Hitbox {
+        pos: Vec3::new(x, y, 0.),
+        width: 20.,
+        height: 20.,
+    };
+    assert_eq!(
+        hitbox(15., 2.).separation(&hitbox(0., 0.)),
+        Vec2::new(5., 0.)
+    );
+    assert_eq!(
+        hitbox(-2., -12.).separation(&hitbox(0., 0.)),
+        Vec2::new(0., -8.)
+    );
+    assert_eq!(hitbox(25., 0.).separation(&hitbox(0., 0.)), Vec2::ZERO);
+    assert_eq!(hitbox(15., 2.).overlap_area(&hitbox(0., 0.)), 5. * 18.);
+}
+
+#[test]
+fn should_only_block_hitboxes_detecting_each_other() {
+    let player = CollisionLayers::new(&[CollisionLayer::Player], &[CollisionLayer::Enemy]);
+    let enemy = CollisionLayers::new(
+        &[CollisionLayer::Enemy],
+        &[CollisionLayer::Player, CollisionLayer::Enemy],
+    );
+    let item = CollisionLayers::new(&[CollisionLayer::Item], &[]);
+
+    assert!(player.blocks(&enemy));
+    assert!(enemy.blocks(&enemy));
+    assert!(!player.blocks(&player));
+    assert!(!player.blocks(&item));
+    assert!(CollisionLayers::ALL.blocks(&enemy));
+    assert!(!CollisionLayers::detecting(&[CollisionLayer::Player]).detects(&enemy));
+
+    // Ghosts are monsters that only detect the player, so they pass through other monsters
+    let ghost = CollisionLayers::new(&[CollisionLayer::Enemy], &[CollisionLayer::Player]);
+    assert!(ghost.blocks(&player));
+    assert!(!ghost.blocks(&enemy));
+    assert!(!enemy.blocks(&ghost));
 }
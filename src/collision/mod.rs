@@ -1,7 +1,17 @@
-use crate::movement::components::BlocksMovement;
+use crate::{
+    events::RuledEventAppExt,
+    movement::components::{IntegrateMovement, MoveAttempt, MoveRule},
+    simulation::{SimulationAppExt, SimulationStage},
+};
 use bevy::prelude::*;
 
-use self::systems::{collides_with_hitbox, update_hitbox_pos};
+use self::{
+    components::{CollisionEnded, CollisionStarted, SensorContacts, SpatialHash},
+    systems::{
+        collides_with_hitbox, detect_sensor_contacts, remove_from_spatial_hash,
+        separate_overlapping_units, update_collider_hitboxes, update_spatial_hash,
+    },
+};
 
 pub mod components;
 pub mod systems;
@@ -10,7 +20,27 @@ pub struct CollisionPlugin;
 
 impl Plugin for CollisionPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system(update_hitbox_pos.before(BlocksMovement))
-            .add_system(collides_with_hitbox);
+        // Spawns and despawns of the last frame are picked up before the next tick moves anything
+        app.init_resource::<SpatialHash>()
+            .init_resource::<SensorContacts>()
+            .add_simulation_event::<CollisionStarted>()
+            .add_simulation_event::<CollisionEnded>()
+            .add_simulation_system_to_stage(SimulationStage::First, remove_from_spatial_hash)
+            .add_simulation_system_to_stage(
+                SimulationStage::First,
+                update_spatial_hash.after(remove_from_spatial_hash),
+            )
+            .add_simulation_system_to_stage(
+                SimulationStage::First,
+                detect_sensor_contacts.after(update_spatial_hash),
+            )
+            .add_simulation_system(separate_overlapping_units.after(IntegrateMovement))
+            .add_simulation_system_to_stage(SimulationStage::PostUpdate, update_collider_hitboxes)
+            .add_system_to_stage(CoreStage::PostUpdate, remove_from_spatial_hash)
+            .add_event_rule::<MoveAttempt, _>(
+                collides_with_hitbox
+                    .label(MoveRule::Hitboxes)
+                    .after(MoveRule::RoomBounds),
+            );
     }
 }
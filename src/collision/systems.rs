@@ -1,32 +1,403 @@
+use std::collections::{BTreeMap, BTreeSet};
+
 use bevy::prelude::*;
 
-use crate::{events::RuledEventQueue, movement::components::MoveAttempt};
+#[cfg(test)]
+use super::components::{collider_bundle, CollisionLayer};
+#[cfg(test)]
+use crate::{
+    events::RuledEventAppExt,
+    map::{
+        components::{map_idx, map_with_floor, Rectangle, TileType},
+        systems::{check_room_boundaries, check_wall_collision},
+    },
+    movement::components::MoveRule,
+    simulation::{simulation_schedule, SimulationAppExt, SimulationStage},
+};
+#[cfg(test)]
+use bevy::ecs::{event::Events, schedule::ShouldRun};
+
+use crate::{
+    events::{RuledEvent, RuledEventQueue},
+    global_components::Rectangular,
+    movement::components::{sample_free_fraction, Mass, MoveAttempt, MoveRejection},
+};
+
+use super::components::{
+    Collider, CollisionEnded, CollisionLayers, CollisionStarted, Hitbox, MovementCollider,
+    MovementColliders, Sensor, SensorContacts, SpatialHash,
+};
+
+/// How far overlapping units are pushed apart at most per tick, so separating looks like a shove
+/// instead of a jump
+const MAX_SEPARATION_STEP: f32 = 2.;
+
+/// Moves the colliders along with their units and fits the ones without a size to the sprite
+pub fn update_collider_hitboxes(
+    units: Query<(&Transform, Option<&Rectangular>)>,
+    mut colliders: Query<(&Parent, &Collider, &mut Hitbox)>,
+) {
+    for (parent, collider, mut hitbox) in colliders.iter_mut() {
+        if let Ok((transform, rectangular)) = units.get(parent.0) {
+            let sprite_bounds = rectangular
+                .map(|r| r.0 * transform.scale.truncate())
+                .unwrap_or_default();
+            let updated = collider.hitbox(transform.translation, sprite_bounds);
+            // Only touch hitboxes that moved so the spatial hash can skip the others
+            if *hitbox != updated {
+                *hitbox = updated;
+            }
+        }
+    }
+}
+
+#[allow(clippy::type_complexity)]
+pub fn update_spatial_hash(
+    mut spatial_hash: ResMut<SpatialHash>,
+    hitboxes: Query<
+        (Entity, &Hitbox, Option<&CollisionLayers>),
+        Or<(Changed<Hitbox>, Changed<CollisionLayers>)>,
+    >,
+) {
+    for (entity, hitbox, layers) in hitboxes.iter() {
+        spatial_hash.insert(
+            entity,
+            hitbox.clone(),
+            layers.copied().unwrap_or(CollisionLayers::ALL),
+        );
+    }
+}
+
+/// Removals are only tracked for one frame, so this runs every frame in addition to every tick
+pub fn remove_from_spatial_hash(
+    mut spatial_hash: ResMut<SpatialHash>,
+    removed: RemovedComponents<Hitbox>,
+) {
+    for entity in removed.iter() {
+        spatial_hash.remove(entity);
+    }
+}
 
-use super::components::Hitbox;
+/// Compares what every sensor overlaps now with the last tick
+pub fn detect_sensor_contacts(
+    spatial_hash: Res<SpatialHash>,
+    mut contacts: ResMut<SensorContacts>,
+    sensors: Query<(Entity, &Hitbox, Option<&CollisionLayers>), With<Sensor>>,
+    mut started_events: EventWriter<CollisionStarted>,
+    mut ended_events: EventWriter<CollisionEnded>,
+) {
+    let mut current = BTreeSet::new();
+    for (sensor, hitbox, layers) in sensors.iter() {
+        let layers = layers.copied().unwrap_or(CollisionLayers::ALL);
+        for (other, _) in spatial_hash.overlapping(hitbox, &layers) {
+            if other != sensor {
+                current.insert((sensor, other));
+            }
+        }
+    }
 
-/// This should be removed in favor of putting the component as a child of the unit
-pub fn update_hitbox_pos(mut query: Query<(&Transform, &mut Hitbox)>) {
-    for (pos, mut hitbox) in query.iter_mut() {
-        hitbox.pos = pos.translation;
+    for &(sensor, other) in current.difference(&contacts.0) {
+        started_events.send(CollisionStarted { sensor, other });
+    }
+    for &(sensor, other) in contacts.0.difference(&current) {
+        // Despawned sensors have nobody left to tell
+        if sensors.get(sensor).is_ok() {
+            ended_events.send(CollisionEnded { sensor, other });
+        }
     }
+    contacts.0 = current;
 }
 
+/// Units are blocked by the movement colliders of other units, reported as the other unit
 pub fn collides_with_hitbox(
     mut move_events: ResMut<RuledEventQueue<MoveAttempt>>,
-    hitboxes: Query<&Hitbox>,
+    spatial_hash: Res<SpatialHash>,
+    movement_colliders: MovementColliders,
+    collision_layers: Query<&CollisionLayers>,
+    parents: Query<&Parent>,
 ) {
+    let layers_of = |entity| {
+        collision_layers
+            .get(entity)
+            .copied()
+            .unwrap_or(CollisionLayers::ALL)
+    };
     for move_attempt in move_events.read_events() {
-        let moving_hitbox = match hitboxes.get(move_attempt.entity) {
-            Ok(moving_entity) => moving_entity,
-            Err(_) => panic!("Hitbox for moving entity not found!"),
+        let entity = move_attempt.entity;
+        let moving_hitbox = match movement_colliders.hitbox_at(entity, move_attempt.origin) {
+            Some(moving_hitbox) => moving_hitbox,
+            None => continue,
+        };
+        let offset = moving_hitbox.pos - move_attempt.origin;
+        let moving_layers = movement_colliders
+            .entity(entity)
+            .map(layers_of)
+            .unwrap_or(CollisionLayers::ALL);
+        // Units that already overlap may move apart but not further into each other
+        let colliding_unit = |destination: Vec3| {
+            let destination_hitbox = Hitbox {
+                pos: destination + offset,
+                ..moving_hitbox.clone()
+            };
+            spatial_hash
+                .overlapping(&destination_hitbox, &moving_layers)
+                .into_iter()
+                .filter(|(other, other_hitbox)| {
+                    movement_colliders.contains(*other)
+                        && layers_of(*other).blocks(&moving_layers)
+                        && destination_hitbox.overlap_area(other_hitbox)
+                            > moving_hitbox.overlap_area(other_hitbox)
+                })
+                .filter_map(|(other, _)| parents.get(other).ok().map(|p| p.0))
+                .find(|unit| *unit != entity)
         };
-        let mut destination_hitbox = moving_hitbox.clone();
-        destination_hitbox.pos = move_attempt.destination;
-        for other_hitbox in hitboxes.iter() {
-            if moving_hitbox != other_hitbox && destination_hitbox.collides_with(other_hitbox) {
-                move_attempt.viable = false;
-                break;
+        if let Some(other) = colliding_unit(move_attempt.destination) {
+            // Stop right in front of the other hitbox instead of keeping a gap
+            if !move_attempt.resolve_per_axis(sample_free_fraction(|destination| {
+                colliding_unit(destination).is_none()
+            })) {
+                move_attempt.reject("collides_with_hitbox", MoveRejection::Hitbox { other });
             }
         }
     }
 }
+
+/// Pushes overlapping units apart, for example after one spawned on top of another. The pushes
+/// are move attempts, so walls and other units can stop them and the rest follows on later ticks.
+#[allow(clippy::type_complexity)]
+pub fn separate_overlapping_units(
+    mut move_events: ResMut<RuledEventQueue<MoveAttempt>>,
+    spatial_hash: Res<SpatialHash>,
+    colliders: Query<(Entity, &Parent, &Hitbox, Option<&CollisionLayers>), With<MovementCollider>>,
+    units: Query<(&Transform, Option<&Mass>)>,
+) {
+    let mass_of = |unit| match units.get(unit) {
+        Ok((_, Some(mass))) => *mass,
+        _ => Mass::default(),
+    };
+    // Ordered so the attempts are queued in the same order every run
+    let mut pushes: BTreeMap<Entity, Vec2> = BTreeMap::new();
+    for (collider, unit, hitbox, layers) in colliders.iter() {
+        let layers = layers.copied().unwrap_or(CollisionLayers::ALL);
+        for (other, other_hitbox) in spatial_hash.overlapping(hitbox, &layers) {
+            // Every pair is handled once, from the collider with the lower entity
+            if other <= collider {
+                continue;
+            }
+            let (other_unit, other_layers) = match colliders.get(other) {
+                Ok((_, other_unit, _, other_layers)) => (
+                    other_unit.0,
+                    other_layers.copied().unwrap_or(CollisionLayers::ALL),
+                ),
+                Err(_) => continue,
+            };
+            if other_unit == unit.0 || !layers.blocks(&other_layers) {
+                continue;
+            }
+            let separation = hitbox.separation(other_hitbox);
+            let (share, other_share) = mass_of(unit.0).push_shares(mass_of(other_unit));
+            *pushes.entry(unit.0).or_default() += separation * share;
+            *pushes.entry(other_unit).or_default() -= separation * other_share;
+        }
+    }
+
+    for (unit, push) in pushes {
+        let push = push.clamp_length_max(MAX_SEPARATION_STEP);
+        if push == Vec2::ZERO {
+            continue;
+        }
+        if let Ok((transform, _)) = units.get(unit) {
+            move_events.add_event(MoveAttempt::new(
+                unit,
+                transform.translation,
+                transform.translation + push.extend(0.),
+            ));
+        }
+    }
+}
+
+#[test]
+fn should_report_start_and_end_of_sensor_contacts() {
+    let mut world = World::new();
+    world.init_resource::<SpatialHash>();
+    world.init_resource::<SensorContacts>();
+    world.init_resource::<Events<CollisionStarted>>();
+    world.init_resource::<Events<CollisionEnded>>();
+    let hitbox_at = |x: f32| Hitbox {
+        pos: Vec3::new(x, 0., 0.),
+        width: 10.,
+        height: 10.,
+    };
+    let sensor = world.spawn().insert(hitbox_at(0.)).insert(Sensor).id();
+    let other = world.spawn().insert(hitbox_at(5.)).id();
+    let mut stage = SystemStage::single_threaded()
+        .with_system(update_spatial_hash)
+        .with_system(detect_sensor_contacts.after(update_spatial_hash));
+
+    stage.run(&mut world);
+    stage.run(&mut world);
+    world.get_mut::<Hitbox>(other).unwrap().pos.x = 50.;
+    stage.run(&mut world);
+
+    let started: Vec<CollisionStarted> = world
+        .resource_mut::<Events<CollisionStarted>>()
+        .drain()
+        .collect();
+    let ended: Vec<CollisionEnded> = world
+        .resource_mut::<Events<CollisionEnded>>()
+        .drain()
+        .collect();
+    assert_eq!(started, vec![CollisionStarted { sensor, other }]);
+    assert_eq!(ended, vec![CollisionEnded { sensor, other }]);
+}
+
+#[test]
+fn should_push_lighter_units_further_apart() {
+    let mut world = World::new();
+    world.init_resource::<SpatialHash>();
+    world.insert_resource(RuledEventQueue::<MoveAttempt>::new());
+    let mut spawn_unit = |pos: Vec3, mass: Mass| {
+        let unit = world
+            .spawn()
+            .insert(Transform::from_translation(pos))
+            .insert(mass)
+            .id();
+        let collider = world
+            .spawn()
+            .insert(Hitbox {
+                pos,
+                width: 20.,
+                height: 20.,
+            })
+            .insert(MovementCollider)
+            .insert(Parent(unit))
+            .id();
+        world.entity_mut(unit).push_children(&[collider]);
+        unit
+    };
+    let light = spawn_unit(Vec3::ZERO, Mass(1.));
+    let heavy = spawn_unit(Vec3::new(18., 0., 0.), Mass(3.));
+    let immovable = spawn_unit(Vec3::new(0., -19., 0.), Mass(f32::INFINITY));
+    let mut stage = SystemStage::single_threaded()
+        .with_system(update_spatial_hash)
+        .with_system(separate_overlapping_units.after(update_spatial_hash));
+
+    stage.run(&mut world);
+
+    let steps: BTreeMap<Entity, Vec3> = world
+        .resource_mut::<RuledEventQueue<MoveAttempt>>()
+        .read_events()
+        .map(|move_attempt| (move_attempt.entity, move_attempt.step()))
+        .collect();
+    assert_eq!(steps[&light], Vec3::new(-1.5, 1., 0.));
+    assert_eq!(steps[&heavy], Vec3::new(0.5, 1., 0.));
+    assert!(!steps.contains_key(&immovable));
+}
+
+#[test]
+fn should_stop_wall_slide_in_front_of_other_unit() {
+    let mut app = App::new();
+    let mut map = map_with_floor(&Rectangle::new(2, 2, 8, 8));
+    // The wall lies right above the moving unit
+    map.tiles[map_idx(5, 6)] = TileType::Wall;
+    app.insert_resource(map)
+        .init_resource::<SpatialHash>()
+        .add_simulation_system_to_stage(SimulationStage::First, update_spatial_hash)
+        // Registered in reverse, the labels decide the order
+        .add_event_rule::<MoveAttempt, _>(
+            collides_with_hitbox
+                .label(MoveRule::Hitboxes)
+                .after(MoveRule::RoomBounds),
+        )
+        .add_event_rule::<MoveAttempt, _>(
+            check_room_boundaries
+                .label(MoveRule::RoomBounds)
+                .after(MoveRule::Walls),
+        )
+        .add_event_rule::<MoveAttempt, _>(check_wall_collision.label(MoveRule::Walls));
+    simulation_schedule(&mut app).set_run_criteria(IntoSystem::into_system(|| ShouldRun::Yes));
+    let origin = Vec3::new(160., 166., 0.);
+    let unit = spawn_test_unit(&mut app.world, origin, CollisionLayers::ALL);
+    // Only touches the diagonal destination but is in the way of sliding along the wall
+    spawn_test_unit(
+        &mut app.world,
+        Vec3::new(181.5, 148., 0.),
+        CollisionLayers::ALL,
+    );
+    app.world
+        .resource_mut::<RuledEventQueue<MoveAttempt>>()
+        .add_event(MoveAttempt::new(
+            unit,
+            origin,
+            origin + Vec3::new(2., 2., 0.),
+        ));
+
+    app.update();
+
+    let mut queue = app.world.resource_mut::<RuledEventQueue<MoveAttempt>>();
+    let move_attempt = queue.read_events().next().unwrap();
+    assert_eq!(move_attempt.destination, Vec3::new(161.5, 166., 0.));
+}
+
+/// A unit with a 20 by 20 movement collider
+#[cfg(test)]
+pub fn spawn_test_unit(world: &mut World, pos: Vec3, layers: CollisionLayers) -> Entity {
+    let unit = world.spawn().insert(Transform::from_translation(pos)).id();
+    let collider = world
+        .spawn()
+        .insert_bundle(collider_bundle(
+            Collider {
+                offset: Vec2::ZERO,
+                size: Some(Vec2::new(20., 20.)),
+            },
+            layers,
+            pos,
+            Vec2::ZERO,
+        ))
+        .insert(MovementCollider)
+        .id();
+    world.entity_mut(unit).push_children(&[collider]);
+    unit
+}
+
+#[test]
+fn should_let_ghosts_pass_through_monsters() {
+    let mut world = World::new();
+    world.init_resource::<SpatialHash>();
+    world.insert_resource(RuledEventQueue::<MoveAttempt>::new());
+    let enemy = CollisionLayers::new(
+        &[CollisionLayer::Enemy],
+        &[CollisionLayer::Player, CollisionLayer::Enemy],
+    );
+    let ghost = CollisionLayers::new(&[CollisionLayer::Enemy], &[CollisionLayer::Player]);
+    let monster = spawn_test_unit(&mut world, Vec3::new(0., 100., 0.), enemy);
+    let ghost = spawn_test_unit(&mut world, Vec3::new(20., 100., 0.), ghost);
+    let blocked = spawn_test_unit(&mut world, Vec3::new(0., 200., 0.), enemy);
+    spawn_test_unit(&mut world, Vec3::new(20., 200., 0.), enemy);
+    let mut stage = SystemStage::single_threaded()
+        .with_system(update_spatial_hash)
+        .with_system(collides_with_hitbox.after(update_spatial_hash));
+    let step = Vec3::new(4., 0., 0.);
+    let mut queue = world.resource_mut::<RuledEventQueue<MoveAttempt>>();
+    queue.add_event(MoveAttempt::new(
+        ghost,
+        Vec3::new(20., 100., 0.),
+        Vec3::new(20., 100., 0.) - step,
+    ));
+    queue.add_event(MoveAttempt::new(
+        blocked,
+        Vec3::new(0., 200., 0.),
+        Vec3::new(0., 200., 0.) + step,
+    ));
+
+    stage.run(&mut world);
+
+    let steps: BTreeMap<Entity, Vec3> = world
+        .resource_mut::<RuledEventQueue<MoveAttempt>>()
+        .read_events()
+        .map(|move_attempt| (move_attempt.entity, move_attempt.step()))
+        .collect();
+    assert_eq!(steps[&ghost], -step);
+    assert!(!steps.contains_key(&blocked));
+    assert!(!steps.contains_key(&monster));
+}
@@ -28,12 +28,57 @@ impl Health {
         self.current_health as f32 / self.max_health as f32
     }
 
+    pub fn heal(&mut self, amount: u32) {
+        self.current_health = u32::min(self.current_health + amount, self.max_health);
+    }
+
     pub fn inflict_damage(&mut self, amount: u32) {
         self.current_health = i32::max(self.current_health as i32 - amount as i32, 0) as u32;
     }
 }
 
+/// Hits are ignored until the timer finished, for example during a dash. Every source only
+/// extends it, so none of them cuts short the invulnerability another one gave.
+#[derive(Component)]
+pub struct Invulnerable(pub Timer);
+
+impl Invulnerable {
+    pub fn new(seconds: f32) -> Self {
+        Self(Timer::from_seconds(seconds, false))
+    }
+
+    /// Lasts at least for the given seconds from now on
+    pub fn extend(&mut self, seconds: f32) {
+        let remaining = self.0.duration() - self.0.elapsed();
+        if remaining.as_secs_f32() < seconds {
+            *self = Self::new(seconds);
+        }
+    }
+}
+
+/// A blow that hurts the target and knocks it away from where the blow came from
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Hit {
+    pub target: Entity,
+    pub from: Vec3,
+    pub damage: u32,
+    /// Strength of the impulse that pushes the target away
+    pub knockback: f32,
+}
+
 #[derive(Component)]
 pub struct MissingHealthUI;
 #[derive(Component)]
 pub struct HealthUI;
+
+#[test]
+fn should_keep_longer_invulnerability() {
+    let mut invulnerable = Invulnerable::new(2.);
+    invulnerable.0.tick(std::time::Duration::from_secs_f32(0.5));
+
+    invulnerable.extend(1.);
+    assert_eq!(invulnerable.0.duration().as_secs_f32(), 2.);
+    invulnerable.extend(3.);
+    assert_eq!(invulnerable.0.duration().as_secs_f32(), 3.);
+    assert_eq!(invulnerable.0.elapsed().as_secs_f32(), 0.);
+}
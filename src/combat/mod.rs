@@ -3,9 +3,18 @@ mod systems;
 
 use bevy::{ecs::schedule::ShouldRun, prelude::*};
 
-use crate::GameState;
+use crate::{
+    simulation::{SimulationAppExt, SimulationStage},
+    GameState,
+};
 
-use self::systems::{render_damage, spawn_health_bar};
+use self::{
+    components::Hit,
+    systems::{
+        apply_hits, knock_back_on_contact, render_damage, spawn_health_bar,
+        wear_off_invulnerability,
+    },
+};
 
 pub struct CombatPlugin;
 
@@ -16,7 +25,14 @@ impl Plugin for CombatPlugin {
                 .with_run_criteria(run_spawn_health_bar)
                 .with_system(spawn_health_bar.exclusive_system().at_end()),
         )
-        .add_system(render_damage);
+        .add_system(render_damage)
+        .add_simulation_event::<Hit>()
+        .add_simulation_system_to_stage(SimulationStage::PostUpdate, knock_back_on_contact)
+        .add_simulation_system_to_stage(SimulationStage::PostUpdate, apply_hits)
+        .add_simulation_system_to_stage(
+            SimulationStage::PostUpdate,
+            wear_off_invulnerability.after(apply_hits),
+        );
     }
 }
 
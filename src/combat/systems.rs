@@ -1,10 +1,36 @@
+use crate::collision::components::{
+    CollisionLayer, CollisionLayers, Hitbox, MovementColliders, SpatialHash,
+};
+use crate::enemy::components::Enemy;
 use crate::global_components::Rectangular;
+use crate::movement::components::{Impulse, Knockback};
+use crate::player::components::Player;
+use crate::simulation::simulation_step;
 
-use super::components::{Health, HealthAssets, HealthUI, MissingHealthUI};
+#[cfg(test)]
+use crate::{
+    collision::systems::{spawn_test_unit, update_spatial_hash},
+    enemy::components::EnemyKind,
+    movement::components::MoveIntent,
+    player::{
+        components::{Dash, DASH_IMPULSE, DASH_INVULNERABILITY},
+        systems::dash,
+    },
+};
+#[cfg(test)]
+use bevy::ecs::event::Events;
+
+use super::components::{Health, HealthAssets, HealthUI, Hit, Invulnerable, MissingHealthUI};
 use bevy::{prelude::Sprite, prelude::*, sprite::Anchor};
 
 const HEALTH_BAR_WIDTH: f32 = 30.0;
 const HEALTH_BAR_X_START: f32 = -(HEALTH_BAR_WIDTH / 2.);
+const CONTACT_KNOCKBACK: f32 = 300.;
+const CONTACT_CONTROL_LOSS: f32 = 0.25;
+/// Hitboxes closer than this count as touching, units stopped by each other never overlap
+const CONTACT_MARGIN: f32 = 1.;
+/// How long a hit target can't move on its own
+const HIT_CONTROL_LOSS: f32 = 0.3;
 
 pub fn spawn_health_bar(
     mut commands: Commands,
@@ -72,3 +98,185 @@ pub fn render_damage(
         }
     }
 }
+
+/// Touching an enemy, by running into it, brushing past it or being pushed, knocks the player
+/// away from it. Every enemy gives one push per tick and none while the player is knocked back.
+pub fn knock_back_on_contact(
+    spatial_hash: Res<SpatialHash>,
+    movement_colliders: MovementColliders,
+    mut impulses: EventWriter<Impulse>,
+    players: Query<(Entity, &Transform, Option<&Knockback>), With<Player>>,
+    enemies: Query<&Transform, With<Enemy>>,
+    parents: Query<&Parent>,
+) {
+    let enemy_layers = CollisionLayers::detecting(&[CollisionLayer::Enemy]);
+    for (player, player_transform, knockback) in players.iter() {
+        if knockback.is_some() {
+            continue;
+        }
+        let reach = match movement_colliders.hitbox_at(player, player_transform.translation) {
+            Some(hitbox) => Hitbox {
+                width: hitbox.width + 2. * CONTACT_MARGIN,
+                height: hitbox.height + 2. * CONTACT_MARGIN,
+                ..hitbox
+            },
+            None => continue,
+        };
+        let touching_enemies = spatial_hash
+            .overlapping(&reach, &enemy_layers)
+            .into_iter()
+            .filter(|(collider, _)| movement_colliders.contains(*collider))
+            .filter_map(|(collider, _)| parents.get(collider).ok())
+            .filter_map(|enemy| enemies.get(enemy.0).ok());
+        for enemy_transform in touching_enemies {
+            let away = (player_transform.translation - enemy_transform.translation)
+                .truncate()
+                .normalize_or_zero();
+            impulses.send(Impulse {
+                entity: player,
+                impulse: away * CONTACT_KNOCKBACK,
+                control_loss: CONTACT_CONTROL_LOSS,
+            });
+        }
+    }
+}
+
+/// Hurts the targets of hits and pushes them away from the blow. Invulnerable targets shrug
+/// hits off completely.
+pub fn apply_hits(
+    mut hits: EventReader<Hit>,
+    mut impulses: EventWriter<Impulse>,
+    mut targets: Query<(&Transform, &mut Health), Without<Invulnerable>>,
+) {
+    for hit in hits.iter() {
+        if let Ok((transform, mut health)) = targets.get_mut(hit.target) {
+            health.inflict_damage(hit.damage);
+            let away = (transform.translation - hit.from)
+                .truncate()
+                .normalize_or_zero();
+            impulses.send(Impulse {
+                entity: hit.target,
+                impulse: away * hit.knockback,
+                control_loss: HIT_CONTROL_LOSS,
+            });
+        }
+    }
+}
+
+pub fn wear_off_invulnerability(
+    mut commands: Commands,
+    mut query: Query<(Entity, &mut Invulnerable)>,
+) {
+    for (entity, mut invulnerable) in query.iter_mut() {
+        if invulnerable.0.tick(simulation_step()).finished() {
+            commands.entity(entity).remove::<Invulnerable>();
+        }
+    }
+}
+
+#[test]
+fn should_knock_back_player_once_per_touching_enemy() {
+    let mut world = World::new();
+    world.init_resource::<Events<Impulse>>();
+    world.init_resource::<SpatialHash>();
+    let player = spawn_test_unit(&mut world, Vec3::ZERO, CollisionLayers::ALL);
+    world.entity_mut(player).insert(Player {
+        idle_atlas: Handle::default(),
+        run_atlas: Handle::default(),
+    });
+    // Touching diagonally at a corner, which a wall slide never rejects
+    let enemy = spawn_test_unit(&mut world, Vec3::new(20., 20., 0.), CollisionLayers::ALL);
+    world.entity_mut(enemy).insert(Enemy);
+    let far_enemy = spawn_test_unit(&mut world, Vec3::new(60., 0., 0.), CollisionLayers::ALL);
+    world.entity_mut(far_enemy).insert(Enemy);
+    let mut stage = SystemStage::single_threaded()
+        .with_system(update_spatial_hash)
+        .with_system(knock_back_on_contact.after(update_spatial_hash));
+
+    stage.run(&mut world);
+
+    let impulses: Vec<Impulse> = world.resource_mut::<Events<Impulse>>().drain().collect();
+    assert_eq!(impulses.len(), 1);
+    assert_eq!(impulses[0].entity, player);
+    assert!(impulses[0].impulse.x < 0. && impulses[0].impulse.y < 0.);
+}
+
+#[test]
+fn should_knock_zombie_away_from_hit() {
+    let mut world = World::new();
+    world.init_resource::<Events<Hit>>();
+    world.init_resource::<Events<Impulse>>();
+    let zombie = world
+        .spawn()
+        .insert(Transform::from_xyz(100., 0., 0.))
+        .insert(Health::new(EnemyKind::BigZombie.base_health()))
+        .id();
+    world.resource_mut::<Events<Hit>>().send(Hit {
+        target: zombie,
+        from: Vec3::new(80., 0., 0.),
+        damage: 3,
+        knockback: 200.,
+    });
+    let mut stage = SystemStage::single_threaded().with_system(apply_hits);
+
+    stage.run(&mut world);
+
+    let impulses: Vec<Impulse> = world.resource_mut::<Events<Impulse>>().drain().collect();
+    assert_eq!(
+        impulses,
+        vec![Impulse {
+            entity: zombie,
+            impulse: Vec2::new(200., 0.),
+            control_loss: HIT_CONTROL_LOSS,
+        }]
+    );
+    let health = world.get::<Health>(zombie).unwrap();
+    assert_eq!(health.current_health, health.max_health - 3);
+
+    world.entity_mut(zombie).insert(Invulnerable::new(1.));
+    world.resource_mut::<Events<Hit>>().send(Hit {
+        target: zombie,
+        from: Vec3::new(80., 0., 0.),
+        damage: 3,
+        knockback: 200.,
+    });
+    stage.run(&mut world);
+    assert_eq!(world.resource_mut::<Events<Impulse>>().drain().count(), 0);
+    let health = world.get::<Health>(zombie).unwrap();
+    assert_eq!(health.current_health, health.max_health - 3);
+}
+
+#[test]
+fn should_ignore_hits_on_dashing_player() {
+    let mut world = World::new();
+    world.init_resource::<Events<Hit>>();
+    world.init_resource::<Events<Impulse>>();
+    let mut player_dash = Dash::new(Some(DASH_INVULNERABILITY));
+    player_dash.requested = true;
+    let player = world
+        .spawn()
+        .insert(Transform::default())
+        .insert(Health::new(10))
+        .insert(player_dash)
+        .insert(MoveIntent(Vec2::X))
+        .insert(TextureAtlasSprite::default())
+        .id();
+    let mut dash_stage = SystemStage::single_threaded().with_system(dash);
+    let mut hit_stage = SystemStage::single_threaded().with_system(apply_hits);
+
+    dash_stage.run(&mut world);
+    assert!(world.get::<Invulnerable>(player).is_some());
+    world.resource_mut::<Events<Hit>>().send(Hit {
+        target: player,
+        from: Vec3::new(-20., 0., 0.),
+        damage: 3,
+        knockback: 200.,
+    });
+    hit_stage.run(&mut world);
+
+    let impulses: Vec<Impulse> = world.resource_mut::<Events<Impulse>>().drain().collect();
+    assert_eq!(impulses.len(), 1);
+    assert_eq!(impulses[0].impulse, Vec2::X * DASH_IMPULSE);
+    let health = world.get::<Health>(player).unwrap();
+    assert_eq!(health.current_health, health.max_health);
+}
@@ -0,0 +1,52 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+
+/// How many rejected move destinations stay visible
+const REMEMBERED_REJECTIONS: usize = 32;
+
+/// Overlay of the collision state, toggled with F3. Everything is drawn where the simulation sees
+/// it, so outlines can lag slightly behind the interpolated sprites.
+#[derive(Default)]
+pub struct DebugRender {
+    pub enabled: bool,
+    rejected_destinations: VecDeque<Vec3>,
+}
+
+impl DebugRender {
+    pub fn remember_rejection(&mut self, destination: Vec3) {
+        if self.rejected_destinations.len() == REMEMBERED_REJECTIONS {
+            self.rejected_destinations.pop_front();
+        }
+        self.rejected_destinations.push_back(destination);
+    }
+
+    pub fn rejected_destinations(&self) -> impl Iterator<Item = &Vec3> {
+        self.rejected_destinations.iter()
+    }
+
+    pub fn toggle(&mut self) {
+        self.enabled = !self.enabled;
+        self.rejected_destinations.clear();
+    }
+}
+
+/// Pooled sprites of the overlay, the ones not needed in a frame are hidden
+#[derive(Component)]
+pub struct DebugShape;
+
+#[test]
+fn should_only_remember_latest_rejections() {
+    let mut debug_render = DebugRender::default();
+    for x in 0..REMEMBERED_REJECTIONS + 2 {
+        debug_render.remember_rejection(Vec3::new(x as f32, 0., 0.));
+    }
+    assert_eq!(
+        debug_render.rejected_destinations().count(),
+        REMEMBERED_REJECTIONS
+    );
+    assert_eq!(
+        debug_render.rejected_destinations().next(),
+        Some(&Vec3::new(2., 0., 0.))
+    );
+}
@@ -0,0 +1,22 @@
+pub mod components;
+pub mod systems;
+
+use bevy::prelude::*;
+
+use crate::simulation::{SimulationAppExt, SimulationStage};
+
+use self::{
+    components::DebugRender,
+    systems::{draw_debug_shapes, record_rejected_moves, toggle_debug_render},
+};
+
+pub struct DebugPlugin;
+
+impl Plugin for DebugPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<DebugRender>()
+            .add_system(toggle_debug_render)
+            .add_simulation_system_to_stage(SimulationStage::PostUpdate, record_rejected_moves)
+            .add_system_to_stage(CoreStage::PostUpdate, draw_debug_shapes);
+    }
+}
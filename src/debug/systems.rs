@@ -0,0 +1,168 @@
+use bevy::prelude::*;
+
+use super::components::{DebugRender, DebugShape};
+use crate::{
+    collision::components::{Hitbox, MovementCollider, Sensor},
+    events::RuledEventQueue,
+    map::components::{tile_span, Map},
+    movement::components::MoveAttempt,
+    TILE_SIZE,
+};
+
+const DEBUG_Z: f32 = 50.;
+const LINE_WIDTH: f32 = 1.;
+const REJECTION_SIZE: f32 = 6.;
+const ROOM_COLOR: Color = Color::rgb(0.2, 0.4, 1.);
+const TILE_COLOR: Color = Color::rgba(1., 0.6, 0., 0.25);
+const MOVEMENT_COLLIDER_COLOR: Color = Color::GREEN;
+const SENSOR_COLOR: Color = Color::YELLOW;
+const HITBOX_COLOR: Color = Color::WHITE;
+const REJECTION_COLOR: Color = Color::RED;
+
+pub fn toggle_debug_render(keys: Res<Input<KeyCode>>, mut debug_render: ResMut<DebugRender>) {
+    if keys.just_pressed(KeyCode::F3) {
+        debug_render.toggle();
+    }
+}
+
+/// Rejected moves are forgotten at the end of the tick, so they are collected while they exist
+pub fn record_rejected_moves(
+    move_events: Res<RuledEventQueue<MoveAttempt>>,
+    mut debug_render: ResMut<DebugRender>,
+) {
+    if !debug_render.enabled {
+        return;
+    }
+    for move_attempt in move_events.rejected_events() {
+        debug_render.remember_rejection(move_attempt.origin + move_attempt.requested_step);
+    }
+}
+
+/// A sprite rectangle of the overlay
+struct DebugRect {
+    center: Vec2,
+    size: Vec2,
+    color: Color,
+}
+
+/// Collects the shapes of the overlay and puts them on a pool of sprites that grows when needed.
+/// Unused sprites of the pool are hidden instead of despawned.
+pub fn draw_debug_shapes(
+    mut commands: Commands,
+    debug_render: Res<DebugRender>,
+    map: Res<Map>,
+    mut shapes: Query<(&mut Transform, &mut Sprite, &mut Visibility), With<DebugShape>>,
+    hitboxes: Query<(&Hitbox, Option<&MovementCollider>, Option<&Sensor>)>,
+) {
+    let mut rects = Vec::new();
+    if debug_render.enabled {
+        for room in map.rooms.iter() {
+            let (center, size) = room.bounds.world_bounds();
+            push_outline(&mut rects, center, size, ROOM_COLOR);
+        }
+        for (hitbox, movement_collider, sensor) in hitboxes.iter() {
+            let color = match (movement_collider, sensor) {
+                (Some(_), _) => MOVEMENT_COLLIDER_COLOR,
+                (None, Some(_)) => SENSOR_COLOR,
+                (None, None) => HITBOX_COLOR,
+            };
+            push_outline(
+                &mut rects,
+                hitbox.pos.truncate(),
+                Vec2::new(hitbox.width, hitbox.height),
+                color,
+            );
+            // Only the movement collider is checked against the tiles
+            if movement_collider.is_some() {
+                let (min, max) = tile_span(hitbox);
+                for y in min.1..=max.1 {
+                    for x in min.0..=max.0 {
+                        rects.push(DebugRect {
+                            center: Vec2::new(x as f32, y as f32) * TILE_SIZE as f32,
+                            size: Vec2::splat(TILE_SIZE as f32),
+                            color: TILE_COLOR,
+                        });
+                    }
+                }
+            }
+        }
+        for destination in debug_render.rejected_destinations() {
+            rects.push(DebugRect {
+                center: destination.truncate(),
+                size: Vec2::splat(REJECTION_SIZE),
+                color: REJECTION_COLOR,
+            });
+        }
+    }
+
+    let mut rects = rects.into_iter();
+    for (mut transform, mut sprite, mut visibility) in shapes.iter_mut() {
+        match rects.next() {
+            Some(rect) => {
+                transform.translation = rect.center.extend(DEBUG_Z);
+                sprite.custom_size = Some(rect.size);
+                sprite.color = rect.color;
+                visibility.is_visible = true;
+            }
+            None => visibility.is_visible = false,
+        }
+    }
+    for rect in rects {
+        commands
+            .spawn_bundle(SpriteBundle {
+                sprite: Sprite {
+                    color: rect.color,
+                    custom_size: Some(rect.size),
+                    ..default()
+                },
+                transform: Transform::from_translation(rect.center.extend(DEBUG_Z)),
+                ..default()
+            })
+            .insert(DebugShape);
+    }
+}
+
+fn push_outline(rects: &mut Vec<DebugRect>, center: Vec2, size: Vec2, color: Color) {
+    let half_size = size / 2.;
+    let horizontal = Vec2::new(size.x, LINE_WIDTH);
+    let vertical = Vec2::new(LINE_WIDTH, size.y);
+    for (offset, size) in [
+        (Vec2::new(0., half_size.y), horizontal),
+        (Vec2::new(0., -half_size.y), horizontal),
+        (Vec2::new(half_size.x, 0.), vertical),
+        (Vec2::new(-half_size.x, 0.), vertical),
+    ] {
+        rects.push(DebugRect {
+            center: center + offset,
+            size,
+            color,
+        });
+    }
+}
+
+#[test]
+fn should_reuse_and_hide_pooled_shapes() {
+    use crate::map::components::{map_with_floor, Rectangle};
+
+    let mut world = World::new();
+    world.insert_resource(map_with_floor(&Rectangle::new(2, 2, 8, 8)));
+    world.insert_resource(DebugRender::default());
+    world.resource_mut::<DebugRender>().toggle();
+    let mut stage = SystemStage::single_threaded().with_system(draw_debug_shapes);
+    let shape_visibility = |world: &mut World| {
+        world
+            .query_filtered::<&Visibility, With<DebugShape>>()
+            .iter(world)
+            .map(|visibility| visibility.is_visible)
+            .collect::<Vec<bool>>()
+    };
+
+    stage.run(&mut world);
+    stage.run(&mut world);
+    // The outline of the room
+    assert_eq!(shape_visibility(&mut world), vec![true; 4]);
+
+    world.resource_mut::<DebugRender>().toggle();
+    stage.run(&mut world);
+    assert_eq!(shape_visibility(&mut world), vec![false; 4]);
+}
@@ -1,13 +1,100 @@
 use bevy::prelude::*;
 use bevy_asset_loader::AssetCollection;
+use serde::Deserialize;
+
+use crate::{
+    map::components::Wading,
+    movement::components::{Mass, MovementStats},
+};
 
 #[derive(Component)]
 pub struct Enemy;
 
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub enum EnemyKind {
+    BigZombie,
+    BigDemon,
+}
+
+impl EnemyKind {
+    pub fn hitbox_size(&self) -> Vec2 {
+        match self {
+            EnemyKind::BigZombie => Vec2::new(30., 30.),
+            EnemyKind::BigDemon => Vec2::new(32., 34.),
+        }
+    }
+
+    /// Demons wade through water, zombies shamble around it
+    pub fn wading(&self) -> Wading {
+        match self {
+            EnemyKind::BigZombie => Wading(16.),
+            EnemyKind::BigDemon => Wading(40.),
+        }
+    }
+
+    pub fn movement_stats(&self) -> MovementStats {
+        match self {
+            EnemyKind::BigZombie => MovementStats {
+                max_speed: 40.,
+                acceleration: 400.,
+                friction: 400.,
+            },
+            EnemyKind::BigDemon => MovementStats {
+                max_speed: 55.,
+                acceleration: 300.,
+                friction: 300.,
+            },
+        }
+    }
+
+    pub fn mass(&self) -> Mass {
+        match self {
+            EnemyKind::BigZombie => Mass(3.),
+            EnemyKind::BigDemon => Mass(5.),
+        }
+    }
+
+    pub fn attack_damage(&self) -> u32 {
+        match self {
+            EnemyKind::BigZombie => 2,
+            EnemyKind::BigDemon => 5,
+        }
+    }
+
+    pub fn base_health(&self) -> u32 {
+        match self {
+            EnemyKind::BigZombie => 20,
+            EnemyKind::BigDemon => 60,
+        }
+    }
+}
+
 #[derive(AssetCollection)]
 pub struct EnemyAssets {
     #[asset(path = "frames/units/big_zombie/run", collection(typed))]
     pub big_zombie_run: Vec<Handle<Image>>,
+    #[asset(path = "frames/units/big_demon/run", collection(typed))]
+    pub big_demon_run: Vec<Handle<Image>>,
+}
+
+/// Run animation of an enemy kind together with the size of its biggest frame
+pub struct EnemyAtlas {
+    pub handle: Handle<TextureAtlas>,
+    pub size: Vec2,
+}
+
+pub struct EnemyAtlases {
+    pub big_zombie: EnemyAtlas,
+    pub big_demon: EnemyAtlas,
+}
+
+impl EnemyAtlases {
+    pub fn get(&self, kind: EnemyKind) -> &EnemyAtlas {
+        match kind {
+            EnemyKind::BigZombie => &self.big_zombie,
+            EnemyKind::BigDemon => &self.big_demon,
+        }
+    }
 }
 
 #[derive(Component, Deref, DerefMut)]
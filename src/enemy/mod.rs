@@ -1,7 +1,7 @@
 use crate::GameState;
 use bevy::prelude::*;
 
-use self::systems::{animate_idle_enemy, spawn_enemy};
+use self::systems::{animate_idle_enemy, build_enemy_atlases};
 
 pub mod components;
 pub mod systems;
@@ -10,7 +10,9 @@ pub struct EnemyPlugin;
 
 impl Plugin for EnemyPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system_set(SystemSet::on_enter(GameState::MapDrawn).with_system(spawn_enemy))
-            .add_system(animate_idle_enemy);
+        app.add_system_set(
+            SystemSet::on_enter(GameState::AssetsDone).with_system(build_enemy_atlases),
+        )
+        .add_system(animate_idle_enemy);
     }
 }
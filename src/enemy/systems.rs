@@ -1,31 +1,54 @@
 use bevy::prelude::*;
 
 use crate::{
-    collision::components::Hitbox,
+    ai::components::{Behaviour, Patrol},
+    collision::components::{
+        collider_bundle, Collider, CollisionLayer, CollisionLayers, MovementCollider,
+    },
     combat::components::Health,
     enemy::components::Enemy,
-    global_components::{Direction, Rectangular},
-    map::components::{Map, RoomBound},
-    movement::components::MovingRandomly,
-    TILE_SIZE,
+    global_components::Rectangular,
+    map::components::RoomBound,
+    movement::components::{MoveIntent, Velocity, Wander},
+    simulation::Interpolated,
 };
 
-use super::components::{AnimationTimer, EnemyAssets};
+use super::components::{AnimationTimer, EnemyAssets, EnemyAtlas, EnemyAtlases, EnemyKind};
 
-pub fn spawn_enemy(
+const HEALTH_PER_DIFFICULTY: u32 = 5;
+
+pub fn build_enemy_atlases(
     mut commands: Commands,
-    map: Res<Map>,
     enemy_assets: Res<EnemyAssets>,
     mut texture_atlases: ResMut<Assets<TextureAtlas>>,
     mut textures: ResMut<Assets<Image>>,
 ) {
+    commands.insert_resource(EnemyAtlases {
+        big_zombie: build_atlas(
+            &enemy_assets.big_zombie_run,
+            &mut texture_atlases,
+            &mut textures,
+        ),
+        big_demon: build_atlas(
+            &enemy_assets.big_demon_run,
+            &mut texture_atlases,
+            &mut textures,
+        ),
+    });
+}
+
+fn build_atlas(
+    frames: &[Handle<Image>],
+    texture_atlases: &mut Assets<TextureAtlas>,
+    textures: &mut Assets<Image>,
+) -> EnemyAtlas {
     let mut texture_atlas_builder = TextureAtlasBuilder::default();
-    for handle in &enemy_assets.big_zombie_run {
+    for handle in frames {
         let texture = textures.get(handle).unwrap();
         texture_atlas_builder.add_texture(handle.clone_weak(), texture);
     }
 
-    let texture_atlas = texture_atlas_builder.finish(&mut textures).unwrap();
+    let texture_atlas = texture_atlas_builder.finish(textures).unwrap();
     let size = texture_atlas
         .textures
         .iter()
@@ -40,43 +63,74 @@ pub fn spawn_enemy(
             acc
         })
         .expect("No textures in texture atlas?!");
-    let atlas_handle = texture_atlases.add(texture_atlas);
+    EnemyAtlas {
+        handle: texture_atlases.add(texture_atlas),
+        size,
+    }
+}
 
-    let mut rooms = map.rooms.clone();
-    rooms.sort_by(|a, b| a.center().0.cmp(&b.center().0));
-    for room in rooms.iter().skip(1) {
-        let (x, y) = room.center();
-        let x = (x * TILE_SIZE as i32) as f32;
-        let y = (y * TILE_SIZE as i32) as f32;
-        let pos = Vec3::new(x, y, 0.4);
-        commands
-            .spawn_bundle(SpriteSheetBundle {
-                transform: Transform {
-                    translation: pos,
-                    scale: Vec3::splat(2.0),
-                    ..default()
-                },
-                sprite: TextureAtlasSprite::new(0),
-                texture_atlas: atlas_handle.clone(),
+/// Enemies in deeper rooms of the dungeon get more health. Enemies with a patrol route walk it
+/// instead of wandering around.
+pub fn spawn_enemy(
+    commands: &mut Commands,
+    enemy_atlases: &EnemyAtlases,
+    kind: EnemyKind,
+    pos: Vec3,
+    difficulty: u32,
+    patrol_route: Option<Vec<Vec3>>,
+) {
+    let atlas = enemy_atlases.get(kind);
+    let behaviour = Behaviour::new(pos, kind.attack_damage());
+    let mut enemy = commands.spawn();
+    match patrol_route.and_then(Patrol::new) {
+        Some(patrol) => enemy.insert(behaviour.patrolling()).insert(patrol),
+        None => enemy.insert(behaviour),
+    };
+    enemy
+        .insert_bundle(SpriteSheetBundle {
+            transform: Transform {
+                translation: pos,
+                scale: Vec3::splat(2.0),
                 ..default()
-            })
-            .insert(AnimationTimer(Timer::from_seconds(0.15, true)))
-            .insert(Enemy)
-            .insert(MovingRandomly {
-                timer: Timer::from_seconds(0.05, true),
-                speed: 2.,
-                current_direction: Direction::Up,
-                step_counter: 0,
-            })
-            .insert(RoomBound)
-            .insert(Rectangular(size))
-            .insert(Hitbox {
+            },
+            sprite: TextureAtlasSprite::new(0),
+            texture_atlas: atlas.handle.clone(),
+            ..default()
+        })
+        .insert(AnimationTimer(Timer::from_seconds(0.15, true)))
+        .insert(Enemy)
+        .insert(Wander::default())
+        .insert(MoveIntent::default())
+        .insert(Velocity::default())
+        .insert(Interpolated::new(pos))
+        .insert(kind.movement_stats())
+        .insert(kind.mass())
+        .insert(kind.wading())
+        .insert(RoomBound)
+        .insert(Rectangular(atlas.size))
+        .insert(Health::new(
+            kind.base_health() + difficulty * HEALTH_PER_DIFFICULTY,
+        ))
+        .with_children(|children| {
+            let layers = CollisionLayers::new(
+                &[CollisionLayer::Enemy],
+                &[CollisionLayer::Player, CollisionLayer::Enemy],
+            );
+            let feet = Collider {
+                offset: Vec2::ZERO,
+                size: Some(kind.hitbox_size()),
+            };
+            let sprite_bounds = atlas.size * 2.;
+            children
+                .spawn_bundle(collider_bundle(feet, layers, pos, sprite_bounds))
+                .insert(MovementCollider);
+            children.spawn_bundle(collider_bundle(
+                Collider::sprite_bounds(),
+                layers,
                 pos,
-                width: 30.,
-                height: 30.,
-            })
-            .insert(Health::new(30));
-    }
+                sprite_bounds,
+            ));
+        });
 }
 
 pub fn animate_idle_enemy(
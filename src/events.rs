@@ -1,26 +1,365 @@
-use std::collections::{vec_deque::IterMut, VecDeque};
+use std::fmt::Debug;
 
-pub trait RuledEvent {
-    fn is_viable(&self) -> bool;
+use bevy::{
+    ecs::schedule::{IntoSystemDescriptor, ShouldRun},
+    prelude::*,
+};
+
+#[cfg(test)]
+use crate::simulation::SimulationAppExt;
+use crate::simulation::{simulation_schedule, SimulationStage};
+
+/// Ruled events belong to the simulation. Their rules run in their own stage of a tick after
+/// `SimulationStage::Update` where the events are produced. The consumers follow in the next stage
+/// once every rule had its say.
+#[derive(Clone, Debug, Hash, PartialEq, Eq, StageLabel)]
+pub enum RuledEventStage {
+    Rules,
+    Consume,
+}
+
+/// How often the rules of an event type run at most per tick when they keep adjusting events
+const MAX_RULE_PASSES: u32 = 4;
+
+/// Why a rule vetoed an event together with the name of the rule that did it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rejection<R> {
+    pub rule: &'static str,
+    pub reason: R,
+}
+
+pub trait RuledEvent: Debug + Send + Sync + 'static {
+    type Reason: Clone + Debug + Send + Sync + 'static;
+
+    fn rejections(&self) -> &[Rejection<Self::Reason>];
+
+    fn reject(&mut self, rule: &'static str, reason: Self::Reason);
+
+    fn is_viable(&self) -> bool {
+        self.rejections().is_empty()
+    }
+
+    /// Combines a newly added event into this pending one if both describe the same thing.
+    /// Returns false if they have to stay separate events.
+    fn merge(&mut self, _other: &Self) -> bool {
+        false
+    }
+
+    /// Whether a rule changed the event instead of vetoing it since the last call. All rules
+    /// check an adjusted event again, so none of them misses what another one changed.
+    fn take_adjusted(&mut self) -> bool {
+        false
+    }
 }
 
+/// Events that are produced, validated by rule systems and consumed exactly once.
+///
+/// Producers add events, rule systems read and veto them and a single consumer drains the
+/// remaining viable ones. Like bevy's `Events<T>` the queue is double buffered: events that were
+/// added too late in a frame to be consumed survive one more [`RuledEventQueue::update`] and are
+/// dropped after that, so nothing is ever replayed.
+/// Vetoed events stay readable through [`RuledEventQueue::rejected_events`] until the next update
+/// and are logged on the debug level.
 pub struct RuledEventQueue<T: RuledEvent> {
-    events: VecDeque<T>,
+    previous_events: Vec<T>,
+    current_events: Vec<T>,
+    rejected_events: Vec<T>,
 }
 
 impl<T: RuledEvent> RuledEventQueue<T> {
     pub fn new() -> Self {
         Self {
-            events: VecDeque::new(),
+            previous_events: Vec::new(),
+            current_events: Vec::new(),
+            rejected_events: Vec::new(),
         }
     }
 
+    /// Adds the event unless a pending event of this frame absorbed it
     pub fn add_event(&mut self, event: T) {
-        self.events.push_back(event);
+        if self.current_events.iter_mut().any(|e| e.merge(&event)) {
+            return;
+        }
+        self.current_events.push(event);
+    }
+
+    /// All pending events that have not been vetoed yet
+    pub fn read_events(&mut self) -> impl Iterator<Item = &mut T> {
+        self.previous_events
+            .iter_mut()
+            .chain(self.current_events.iter_mut())
+            .filter(|e| e.is_viable())
+    }
+
+    /// Removes all pending events and hands out the viable ones. The vetoed ones are kept as
+    /// rejected events right away, no matter how much of the result the consumer reads.
+    pub fn consume_events(&mut self) -> std::vec::IntoIter<T> {
+        let (viable_events, rejected_events): (Vec<T>, Vec<T>) = self
+            .previous_events
+            .drain(..)
+            .chain(self.current_events.drain(..))
+            .partition(|e| e.is_viable());
+        for event in rejected_events {
+            for rejection in event.rejections() {
+                debug!(
+                    "{:?} rejected by {}: {:?}",
+                    event, rejection.rule, rejection.reason
+                );
+            }
+            self.rejected_events.push(event);
+        }
+        viable_events.into_iter()
+    }
+
+    /// Events that were vetoed when they were consumed since the last update
+    pub fn rejected_events(&self) -> impl Iterator<Item = &T> {
+        self.rejected_events.iter()
+    }
+
+    /// Swaps the buffers, dropping events that have not been consumed for two updates
+    pub fn update(&mut self) {
+        std::mem::swap(&mut self.previous_events, &mut self.current_events);
+        self.current_events.clear();
+        self.rejected_events.clear();
+    }
+
+    pub fn update_system(mut queue: ResMut<Self>) {
+        queue.update();
+    }
+
+    fn take_adjusted(&mut self) -> bool {
+        let mut adjusted = false;
+        for event in self.read_events() {
+            adjusted |= event.take_adjusted();
+        }
+        adjusted
+    }
+
+    /// Runs the rules once per tick and again as long as one of them adjusted an event
+    fn run_rules_again(mut passes: Local<u32>, mut queue: ResMut<Self>) -> ShouldRun {
+        let adjusted = queue.take_adjusted();
+        if *passes == 0 || (adjusted && *passes < MAX_RULE_PASSES) {
+            *passes += 1;
+            ShouldRun::YesAndCheckAgain
+        } else {
+            *passes = 0;
+            ShouldRun::No
+        }
+    }
+}
+
+/// Labels the run criteria shared by all rules of an event type
+fn rules_of<T: RuledEvent>() -> &'static str {
+    std::any::type_name::<T>()
+}
+
+pub trait RuledEventAppExt {
+    /// Sets up the queue of a ruled event and clears it at the end of every simulation tick.
+    /// Registering the same event twice does nothing.
+    fn add_ruled_event<T: RuledEvent>(&mut self) -> &mut Self;
+
+    /// Adds a system that validates events of the queue before they are consumed. Rules that depend
+    /// on each other are ordered with labels. As long as a rule adjusts an event all rules of the
+    /// event run again, up to a few times per tick.
+    fn add_event_rule<T: RuledEvent, Params>(
+        &mut self,
+        rule: impl IntoSystemDescriptor<Params>,
+    ) -> &mut Self;
+
+    /// Adds the system that drains the viable events of the queue
+    fn add_event_consumer<T: RuledEvent, Params>(
+        &mut self,
+        consumer: impl IntoSystemDescriptor<Params>,
+    ) -> &mut Self;
+}
+
+impl RuledEventAppExt for App {
+    fn add_ruled_event<T: RuledEvent>(&mut self) -> &mut Self {
+        let schedule = simulation_schedule(self);
+        if schedule
+            .get_stage::<SystemStage>(&RuledEventStage::Rules)
+            .is_none()
+        {
+            schedule
+                .add_stage_after(
+                    SimulationStage::Update,
+                    RuledEventStage::Rules,
+                    SystemStage::single_threaded(),
+                )
+                .add_stage_after(
+                    RuledEventStage::Rules,
+                    RuledEventStage::Consume,
+                    SystemStage::single_threaded(),
+                );
+        }
+        if self.world.contains_resource::<RuledEventQueue<T>>() {
+            return self;
+        }
+        let schedule = simulation_schedule(self);
+        schedule.add_system_to_stage(SimulationStage::Last, RuledEventQueue::<T>::update_system);
+        schedule.stage(RuledEventStage::Rules, |stage: &mut SystemStage| {
+            stage.add_system_run_criteria(
+                RuledEventQueue::<T>::run_rules_again.label(rules_of::<T>()),
+            )
+        });
+        self.insert_resource(RuledEventQueue::<T>::new())
+    }
+
+    fn add_event_rule<T: RuledEvent, Params>(
+        &mut self,
+        rule: impl IntoSystemDescriptor<Params>,
+    ) -> &mut Self {
+        self.add_ruled_event::<T>();
+        simulation_schedule(self).add_system_set_to_stage(
+            RuledEventStage::Rules,
+            SystemSet::new()
+                .with_run_criteria(rules_of::<T>())
+                .with_system(rule),
+        );
+        self
+    }
+
+    fn add_event_consumer<T: RuledEvent, Params>(
+        &mut self,
+        consumer: impl IntoSystemDescriptor<Params>,
+    ) -> &mut Self {
+        self.add_ruled_event::<T>();
+        simulation_schedule(self).add_system_to_stage(RuledEventStage::Consume, consumer);
+        self
+    }
+}
+
+#[cfg(test)]
+#[derive(Debug)]
+struct TestEvent {
+    id: u32,
+    rejections: Vec<Rejection<&'static str>>,
+}
+
+#[cfg(test)]
+impl TestEvent {
+    fn new(id: u32) -> Self {
+        Self {
+            id,
+            rejections: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl RuledEvent for TestEvent {
+    type Reason = &'static str;
+
+    fn rejections(&self) -> &[Rejection<Self::Reason>] {
+        &self.rejections
     }
 
-    pub fn read_events(&mut self) -> IterMut<T> {
-        self.events.retain(|e| e.is_viable());
-        self.events.iter_mut()
+    fn reject(&mut self, rule: &'static str, reason: Self::Reason) {
+        self.rejections.push(Rejection { rule, reason });
     }
 }
+
+#[test]
+fn should_consume_only_viable_events_once() {
+    let mut queue = RuledEventQueue::new();
+    queue.add_event(TestEvent::new(1));
+    queue.add_event(TestEvent::new(2));
+    for event in queue.read_events().filter(|e| e.id == 2) {
+        event.reject("test_rule", "too large");
+    }
+
+    let consumed: Vec<u32> = queue.consume_events().map(|e| e.id).collect();
+    assert_eq!(consumed, vec![1]);
+    assert_eq!(queue.consume_events().count(), 0);
+}
+
+#[test]
+fn should_keep_rejected_events_with_their_reasons_until_update() {
+    let mut queue = RuledEventQueue::new();
+    queue.add_event(TestEvent::new(1));
+    for event in queue.read_events() {
+        event.reject("test_rule", "too large");
+    }
+    queue.consume_events().for_each(drop);
+
+    let rejected: Vec<&TestEvent> = queue.rejected_events().collect();
+    assert_eq!(rejected.len(), 1);
+    assert_eq!(
+        rejected[0].rejections(),
+        &[Rejection {
+            rule: "test_rule",
+            reason: "too large"
+        }]
+    );
+    queue.update();
+    assert_eq!(queue.rejected_events().count(), 0);
+}
+
+#[test]
+fn should_record_rejected_events_when_consumer_stops_early() {
+    let mut queue = RuledEventQueue::new();
+    queue.add_event(TestEvent::new(1));
+    queue.add_event(TestEvent::new(2));
+    queue.add_event(TestEvent::new(3));
+    for event in queue.read_events().filter(|e| e.id == 3) {
+        event.reject("test_rule", "too large");
+    }
+
+    let first = queue.consume_events().next().map(|e| e.id);
+    assert_eq!(first, Some(1));
+    let rejected: Vec<u32> = queue.rejected_events().map(|e| e.id).collect();
+    assert_eq!(rejected, vec![3]);
+}
+
+#[test]
+fn should_drop_unconsumed_events_after_two_updates() {
+    let mut queue = RuledEventQueue::new();
+    queue.add_event(TestEvent::new(1));
+
+    queue.update();
+    assert_eq!(queue.read_events().count(), 1);
+    queue.update();
+    assert_eq!(queue.read_events().count(), 0);
+    assert_eq!(queue.consume_events().count(), 0);
+}
+
+#[cfg(test)]
+fn produce_test_events(mut queue: ResMut<RuledEventQueue<TestEvent>>) {
+    queue.add_event(TestEvent::new(1));
+    queue.add_event(TestEvent::new(2));
+}
+
+#[cfg(test)]
+fn reject_even_test_events(mut queue: ResMut<RuledEventQueue<TestEvent>>) {
+    for event in queue.read_events().filter(|e| e.id % 2 == 0) {
+        event.reject("reject_even_test_events", "even");
+    }
+}
+
+#[cfg(test)]
+struct ConsumedTestEvents(Vec<u32>);
+
+#[cfg(test)]
+fn consume_test_events(
+    mut queue: ResMut<RuledEventQueue<TestEvent>>,
+    mut consumed: ResMut<ConsumedTestEvents>,
+) {
+    consumed.0.extend(queue.consume_events().map(|e| e.id));
+}
+
+#[test]
+fn should_run_registered_rules_before_consumer() {
+    let mut app = App::new();
+    app.insert_resource(ConsumedTestEvents(Vec::new()))
+        .add_event_consumer::<TestEvent, _>(consume_test_events)
+        .add_event_rule::<TestEvent, _>(reject_even_test_events)
+        .add_ruled_event::<TestEvent>()
+        .add_simulation_system(produce_test_events);
+    // Tick once per update instead of waiting for real time to pass
+    simulation_schedule(&mut app).set_run_criteria(IntoSystem::into_system(|| ShouldRun::Yes));
+
+    app.update();
+    app.update();
+
+    assert_eq!(app.world.resource::<ConsumedTestEvents>().0, vec![1, 1]);
+}
@@ -8,6 +8,16 @@ pub enum Direction {
     Right,
 }
 
+impl Direction {
+    pub fn to_vec2(self) -> Vec2 {
+        match self {
+            Direction::Up => Vec2::Y,
+            Direction::Down => -Vec2::Y,
+            Direction::Left => -Vec2::X,
+            Direction::Right => Vec2::X,
+        }
+    }
+}
+
 #[derive(Component)]
 pub struct Rectangular(pub Vec2);
-
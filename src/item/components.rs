@@ -0,0 +1,33 @@
+use bevy::prelude::*;
+use bevy_asset_loader::AssetCollection;
+use serde::Deserialize;
+
+#[derive(Component)]
+pub struct Item;
+
+/// Coins the player picked up
+#[derive(Component, Default)]
+pub struct Wallet(pub u32);
+
+#[derive(Component, Clone, Copy, Debug, PartialEq, Deserialize)]
+pub enum ItemKind {
+    HealthFlask,
+    Coin,
+}
+
+impl ItemKind {
+    pub fn size(&self) -> Vec2 {
+        match self {
+            ItemKind::HealthFlask => Vec2::new(16., 16.),
+            ItemKind::Coin => Vec2::new(8., 8.),
+        }
+    }
+}
+
+#[derive(AssetCollection)]
+pub struct ItemAssets {
+    #[asset(path = "frames/flask_big_red.png")]
+    pub health_flask: Handle<Image>,
+    #[asset(path = "frames/coin_anim_f0.png")]
+    pub coin: Handle<Image>,
+}
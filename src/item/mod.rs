@@ -0,0 +1,16 @@
+pub mod components;
+pub mod systems;
+
+use bevy::prelude::*;
+
+use crate::simulation::SimulationAppExt;
+
+use self::systems::pick_up_items;
+
+pub struct ItemPlugin;
+
+impl Plugin for ItemPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_simulation_system(pick_up_items);
+    }
+}
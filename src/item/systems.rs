@@ -0,0 +1,74 @@
+use bevy::prelude::*;
+
+use crate::{
+    collision::components::{
+        collider_bundle, Collider, CollisionLayer, CollisionLayers, CollisionStarted, Sensor,
+    },
+    combat::components::Health,
+    global_components::Rectangular,
+    player::components::Player,
+};
+
+use super::components::{Item, ItemAssets, ItemKind, Wallet};
+
+const HEALTH_PER_FLASK: u32 = 10;
+
+pub fn spawn_item(commands: &mut Commands, item_assets: &ItemAssets, kind: ItemKind, pos: Vec3) {
+    let texture = match kind {
+        ItemKind::HealthFlask => item_assets.health_flask.clone(),
+        ItemKind::Coin => item_assets.coin.clone(),
+    };
+    commands
+        .spawn_bundle(SpriteBundle {
+            texture,
+            transform: Transform {
+                translation: pos,
+                scale: Vec3::splat(2.0),
+                ..default()
+            },
+            ..Default::default()
+        })
+        .insert(Item)
+        .insert(kind)
+        .insert(Rectangular(kind.size()))
+        .with_children(|children| {
+            children
+                .spawn_bundle(collider_bundle(
+                    Collider::sprite_bounds(),
+                    CollisionLayers::new(&[CollisionLayer::Item], &[CollisionLayer::Player]),
+                    pos,
+                    kind.size() * 2.,
+                ))
+                .insert(Sensor);
+        });
+}
+
+/// Items are picked up as soon as any collider of the player touches them
+pub fn pick_up_items(
+    mut commands: Commands,
+    mut collisions: EventReader<CollisionStarted>,
+    parents: Query<&Parent>,
+    items: Query<&ItemKind, With<Item>>,
+    mut players: Query<(&mut Health, &mut Wallet), With<Player>>,
+) {
+    let mut picked_up = Vec::new();
+    for collision in collisions.iter() {
+        let (item, player) = match (parents.get(collision.sensor), parents.get(collision.other)) {
+            (Ok(item), Ok(player)) => (item.0, player.0),
+            _ => continue,
+        };
+        if picked_up.contains(&item) {
+            continue;
+        }
+        let (kind, (mut health, mut wallet)) = match (items.get(item), players.get_mut(player)) {
+            (Ok(kind), Ok(player)) => (kind, player),
+            _ => continue,
+        };
+        match kind {
+            ItemKind::HealthFlask => health.heal(HEALTH_PER_FLASK),
+            ItemKind::Coin => wallet.0 += 1,
+        }
+        commands.entity(item).despawn_recursive();
+        picked_up.push(item);
+    }
+}
@@ -1,17 +1,23 @@
+mod ai;
 mod collision;
 mod combat;
+mod debug;
 mod enemy;
 mod events;
 mod global_components;
+mod item;
 mod map;
 mod movement;
 mod player;
+mod simulation;
+mod spawn;
 
 use crate::map::MapPlugin;
-use bevy::{
-    diagnostic::{FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin},
-    prelude::*,
+use ai::{
+    components::{Behaviour, Patrol},
+    AiPlugin,
 };
+use bevy::prelude::*;
 use bevy_asset_loader::AssetLoader;
 use bevy_inspector_egui::{RegisterInspectable, WorldInspectorPlugin};
 use collision::CollisionPlugin;
@@ -19,10 +25,14 @@ use combat::{
     components::{Health, HealthAssets},
     CombatPlugin,
 };
+use debug::DebugPlugin;
 use enemy::{components::EnemyAssets, EnemyPlugin};
+use item::{components::ItemAssets, ItemPlugin};
 use map::components::MapAssets;
 use movement::MovementPlugin;
 use player::{components::PlayerAssets, PlayerPlugin};
+use simulation::SimulationPlugin;
+use spawn::{components::SpawnAssets, SpawnPlugin};
 
 pub const WINDOW_WIDTH: usize = 1600;
 pub const WINDOW_HEIGHT: usize = 900;
@@ -34,6 +44,7 @@ pub const NUM_TILES: usize = (MAP_WIDTH * MAP_HEIGHT) as usize;
 const NUM_ROOMS: usize = 5;
 const MAX_ROOM_WIDTH: usize = 15;
 const MAX_ROOM_HEIGHT: usize = 15;
+const CORRIDOR_WIDTH: i32 = 2;
 
 fn main() {
     if NUM_ROOMS * MAX_ROOM_HEIGHT * MAX_ROOM_WIDTH > NUM_TILES {
@@ -46,6 +57,8 @@ fn main() {
         .with_collection::<PlayerAssets>()
         .with_collection::<EnemyAssets>()
         .with_collection::<HealthAssets>()
+        .with_collection::<ItemAssets>()
+        .with_collection::<SpawnAssets>()
         .build(&mut app);
     app.insert_resource(WindowDescriptor {
         title: "Dungeon Digger".to_string(),
@@ -56,16 +69,21 @@ fn main() {
     .insert_resource(ClearColor(Color::rgb(0., 0., 0.)))
     .add_state(GameState::AssetLoading)
     .add_plugins(DefaultPlugins)
+    .add_plugin(SimulationPlugin)
     .add_plugin(MapPlugin)
     .add_plugin(PlayerPlugin)
     .add_plugin(EnemyPlugin)
+    .add_plugin(SpawnPlugin)
+    .add_plugin(ItemPlugin)
     .add_plugin(CollisionPlugin)
     .add_plugin(MovementPlugin)
+    .add_plugin(AiPlugin)
     .add_plugin(CombatPlugin)
+    .add_plugin(DebugPlugin)
     .add_plugin(WorldInspectorPlugin::new())
-    .register_inspectable::<Health>() //
-    //.add_plugin(LogDiagnosticsPlugin::default())
-    //.add_plugin(FrameTimeDiagnosticsPlugin::default())
+    .register_inspectable::<Health>()
+    .register_inspectable::<Behaviour>()
+    .register_inspectable::<Patrol>()
     .add_startup_system(setup_camera)
     .run();
 }
@@ -1,7 +1,11 @@
-use crate::{global_components::Direction, MAX_ROOM_HEIGHT, MAX_ROOM_WIDTH};
+use super::dijkstra::DijkstraMap;
+use crate::{collision::components::Hitbox, CORRIDOR_WIDTH, MAX_ROOM_HEIGHT, MAX_ROOM_WIDTH};
 use bevy::math::{Vec2, Vec3};
 use bevy::prelude::*;
 use rand::Rng;
+#[cfg(test)]
+use rand::{rngs::StdRng, SeedableRng};
+use serde::Deserialize;
 use std::cmp::{max, min};
 
 use crate::{MAP_HEIGHT, MAP_WIDTH, NUM_ROOMS, NUM_TILES, TILE_SIZE};
@@ -32,7 +36,7 @@ pub struct MapAssets {
     #[asset(path = "frames/environment/wall/wall_inner_corner_l_top_left.png")]
     wall_inner_corner_top_right: Handle<Image>,
     #[asset(path = "frames/environment/special_floor/floor_ladder.png")]
-    _ladder: Handle<Image>,
+    ladder: Handle<Image>,
 }
 
 enum WallType {
@@ -58,12 +62,115 @@ enum CornerType {
 pub enum TileType {
     Wall,
     Floor,
+    /// Sticky ground that slows units down
+    Mud,
+    /// Slippery ground units hardly speed up or stop on
+    Ice,
+    /// Too deep for units that can't wade through it
+    Water,
     Void,
 }
 
+impl TileType {
+    /// Tiles units can stand on, however the terrain slows them down
+    pub fn is_ground(self) -> bool {
+        matches!(
+            self,
+            TileType::Floor | TileType::Mud | TileType::Ice | TileType::Water
+        )
+    }
+
+    /// Ground that isn't deeper than a unit can wade
+    pub fn is_passable(self, wading: Wading) -> bool {
+        self.is_ground() && wading.0 >= self.terrain().depth
+    }
+
+    pub fn terrain(self) -> Terrain {
+        match self {
+            TileType::Mud => Terrain {
+                speed: 0.5,
+                traction: 2.,
+                depth: 0.,
+            },
+            TileType::Ice => Terrain {
+                speed: 1.,
+                traction: 0.15,
+                depth: 0.,
+            },
+            TileType::Water => Terrain {
+                speed: 0.6,
+                traction: 1.,
+                depth: 24.,
+            },
+            _ => Terrain::default(),
+        }
+    }
+}
+
+/// How the ground changes the movement of the units on it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Terrain {
+    /// Factor of the length of the moves on it
+    pub speed: f32,
+    /// Factor of acceleration and friction, units slide on low traction
+    pub traction: f32,
+    /// Units that can't wade this deep can't enter
+    pub depth: f32,
+}
+
+impl Default for Terrain {
+    fn default() -> Self {
+        Self {
+            speed: 1.,
+            traction: 1.,
+            depth: 0.,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+pub enum RoomType {
+    Start,
+    Normal,
+    Treasure,
+    Boss,
+    Exit,
+}
+
 #[derive(Component)]
 pub struct RoomBound;
 
+/// How deep a unit can wade into water in pixels, units without one stay out of it
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq)]
+pub struct Wading(pub f32);
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TunnelStyle {
+    /// A horizontal and a vertical tunnel meeting at a right angle
+    LShaped,
+    /// A staircase heading straight for the next room
+    Diagonal,
+    /// A random walk that drifts towards the next room
+    Winding,
+    /// Picks one of the other styles for every corridor
+    Random,
+}
+
+pub struct CorridorSettings {
+    /// Width of the corridors in tiles
+    pub width: i32,
+    pub style: TunnelStyle,
+}
+
+impl Default for CorridorSettings {
+    fn default() -> Self {
+        Self {
+            width: CORRIDOR_WIDTH,
+            style: TunnelStyle::Random,
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Rectangle {
     x: i32,
@@ -86,6 +193,24 @@ impl Rectangle {
         (self.x + self.width / 2, self.y + self.height / 2)
     }
 
+    /// Center and size of the rectangle in world coordinates, covering its tiles completely
+    pub fn world_bounds(&self) -> (Vec2, Vec2) {
+        let size = Vec2::new(self.width as f32, self.height as f32) * TILE_SIZE as f32;
+        let min = (Vec2::new(self.x as f32, self.y as f32) - 0.5) * TILE_SIZE as f32;
+        (min + size / 2., size)
+    }
+
+    /// The corners one tile inside the rectangle as a loop, or its outer corners if it is too
+    /// narrow for that
+    pub fn inner_corners(&self) -> [(i32, i32); 4] {
+        let inset = |width: i32| if width >= 3 { 1 } else { 0 };
+        let (inset_x, inset_y) = (inset(self.width), inset(self.height));
+        let (min, max) = (self.min(), self.max());
+        let (left, right) = (min.0 + inset_x, max.0 - inset_x);
+        let (bottom, top) = (min.1 + inset_y, max.1 - inset_y);
+        [(left, bottom), (right, bottom), (right, top), (left, top)]
+    }
+
     fn min(&self) -> (i32, i32) {
         (self.x, self.y)
     }
@@ -94,6 +219,13 @@ impl Rectangle {
         (self.x + self.width - 1, self.y + self.height - 1)
     }
 
+    pub fn random_tile(&self, rng: &mut impl Rng) -> (i32, i32) {
+        (
+            rng.gen_range(self.min().0..=self.max().0),
+            rng.gen_range(self.min().1..=self.max().1),
+        )
+    }
+
     fn touches(&self, other_rect: &Rectangle) -> bool {
         i32::abs(self.x - other_rect.max().0) < 4
             || i32::abs(self.max().0 - other_rect.x) < 4
@@ -116,12 +248,50 @@ impl Rectangle {
     }
 }
 
+#[derive(Clone, Debug)]
+pub struct Room {
+    pub bounds: Rectangle,
+    pub room_type: RoomType,
+    /// Rank of the room by walking distance from the start room which has a difficulty of 0
+    pub difficulty: u32,
+}
+
 pub struct Map {
     pub tiles: Vec<TileType>,
-    pub rooms: Vec<Rectangle>,
+    pub rooms: Vec<Room>,
     pub player_start_pos: Vec2,
+    pub exit_pos: Vec2,
 }
 
+/// How many levels the player went down, starting with 1
+pub struct DungeonDepth(pub u32);
+
+/// Distances of all floor tiles to the tile the player currently stands on.
+/// All enemies share it, so it leads around water even for those that could wade through.
+pub struct PlayerDistanceMap {
+    pub distances: DijkstraMap,
+    pub player_idx: usize,
+}
+
+impl PlayerDistanceMap {
+    pub fn new(map: &Map) -> Self {
+        let player_idx = map_idx_f32(map.player_start_pos.x, map.player_start_pos.y);
+        Self {
+            distances: DijkstraMap::wading(&map.tiles, &[player_idx], Wading::default()),
+            player_idx,
+        }
+    }
+}
+
+const MAX_ROOM_PLACEMENT_ATTEMPTS: usize = 1000;
+/// Hitboxes that just touch a tile with their edge don't overlap it
+const EDGE_TOLERANCE: f32 = 0.001;
+/// Chance of a room other than the start and exit to hold a patch of mud, ice or water
+const TERRAIN_PATCH_CHANCE: f64 = 0.5;
+const MUD_COLOR: Color = Color::rgba(0.35, 0.22, 0.1, 0.7);
+const ICE_COLOR: Color = Color::rgba(0.7, 0.9, 1., 0.5);
+const WATER_COLOR: Color = Color::rgba(0.1, 0.3, 0.8, 0.7);
+
 pub fn map_idx(x: i32, y: i32) -> usize {
     (y * MAP_WIDTH + x) as usize
 }
@@ -139,28 +309,35 @@ pub fn get_coordinate_from_index(index: usize) -> (i32, i32) {
     (x, y)
 }
 
-pub fn map_idx_f32(x: f32, y: f32) -> usize {
-    /*
-    let remainder = x % TILE_SIZE as f32;
-    let mut x = x as usize / TILE_SIZE;
-    if x as i32 > MAP_WIDTH / 2 && remainder > 0. {
-        x += 1;
-    }
-    */
-
-    let x = x as usize / TILE_SIZE;
-    let y = y as usize / TILE_SIZE;
+/// Tiles are drawn centered on their coordinates times the tile size, so every tile reaches half a
+/// tile into each direction
+pub fn tile_coordinate_f32(world: f32) -> i32 {
+    let tile_size = TILE_SIZE as f32;
+    ((world + tile_size / 2.) / tile_size).floor() as i32
+}
 
-    y * MAP_WIDTH as usize + x
+pub fn map_idx_f32(x: f32, y: f32) -> usize {
+    map_idx(
+        tile_coordinate_f32(x).clamp(0, MAP_WIDTH - 1),
+        tile_coordinate_f32(y).clamp(0, MAP_HEIGHT - 1),
+    )
 }
 
 impl Map {
-    pub fn new() -> Self {
+    pub fn new(corridors: CorridorSettings, rng: &mut impl Rng) -> Self {
         let mut player_starting_x = 0;
         let mut player_starting_y = 0;
         let mut rooms = Vec::new();
+        let mut attempts = 0;
         while rooms.len() < NUM_ROOMS {
-            let room = generate_random_rectangle();
+            // The first rooms can block every spot for the remaining ones so start over
+            attempts += 1;
+            if attempts > MAX_ROOM_PLACEMENT_ATTEMPTS {
+                attempts = 0;
+                rooms.clear();
+                player_starting_x = 0;
+            }
+            let room = generate_random_rectangle(rng);
             let mut overlap_or_touch = false;
             for r in &rooms {
                 if room.intersects(r) || room.touches(r) {
@@ -169,19 +346,19 @@ impl Map {
                 }
             }
             // Only take a room if its potential paths to other rooms has enough space from other walls
-            // So just make sure the center of the new room is at least 4 units away from all other room bounds
-            // 4 units are enough for floors and walls
+            // So just make sure the center of the new room is far enough away from all other room bounds
+            // 3 units more than the corridor width are enough for floors and walls
             let mut corridor_too_close_to_walls = false;
             for r in &rooms {
-                if room.center_x_units_away_from_bounds(r, 4) {
+                if room.center_x_units_away_from_bounds(r, corridors.width + 3) {
                     corridor_too_close_to_walls = true;
                     break;
                 }
             }
             if !overlap_or_touch
                 && !corridor_too_close_to_walls
-                && room.max().0 < MAP_WIDTH as i32
-                && room.max().1 < MAP_HEIGHT as i32
+                && room.max().0 < MAP_WIDTH
+                && room.max().1 < MAP_HEIGHT
             {
                 if room.center().0 < player_starting_x || player_starting_x == 0 {
                     (player_starting_x, player_starting_y) = room.center();
@@ -195,9 +372,17 @@ impl Map {
             set_room_tiles(&mut tiles, room);
         }
 
-        build_corridors(&mut tiles, &rooms);
+        build_corridors(&mut tiles, &rooms, &corridors, rng);
         set_walls(&mut tiles);
 
+        // The exit is placed on the tile that takes the longest walk from the start
+        let start_idx = map_idx(player_starting_x, player_starting_y);
+        let distances = DijkstraMap::new(&tiles, &[start_idx]);
+        let exit_idx = distances.farthest().unwrap_or(start_idx);
+        let (exit_x, exit_y) = get_coordinate_from_index(exit_idx);
+        let rooms = classify_rooms(rooms, &distances, exit_idx, rng);
+        place_terrain_patches(&mut tiles, &rooms, rng);
+
         Self {
             tiles,
             rooms,
@@ -205,52 +390,164 @@ impl Map {
                 (player_starting_x * TILE_SIZE as i32) as f32,
                 (player_starting_y * TILE_SIZE as i32) as f32,
             ),
+            exit_pos: Vec2::new(
+                (exit_x * TILE_SIZE as i32) as f32,
+                (exit_y * TILE_SIZE as i32) as f32,
+            ),
         }
     }
 
     /// This should use proper collision algorithm
     pub fn within_room(&self, destination: Vec3) -> bool {
-        let (target_x, target_y) = get_coordinate_from_index(map_idx_f32(destination.x, destination.y));
+        let (target_x, target_y) =
+            get_coordinate_from_index(map_idx_f32(destination.x, destination.y));
         let target_rectangle = Rectangle::new(target_x, target_y, 1, 1);
         for room in self.rooms.iter() {
-            if room.intersects(&target_rectangle) {
+            if room.bounds.intersects(&target_rectangle) {
                 return true;
             }
         }
         false
     }
 
-    pub fn can_enter_tile_f32(&self, destination: Vec3, dir: Direction) -> bool {
-        // This should be heavily refactored to function with the sizes of the unit and
-        // the tiles. Both are anchored with their center causing problems calculating the collision.
-        // It might be worth to implement the bevy collision function in the future.
-        // Also remember to allow a small steps on lower walls because the player sprite is bigger than
-        // a tile so the player can go through tunnels.
-        // If the sprites were drawn with bottom left anchor it would also be possible to use the
-        // intersect function self written for Rectangle using the translation and size.
-        let mut x = destination.x;
-        let mut y = destination.y;
-        match dir {
-            Direction::Right => x += 30.,
-            //Direction::Left => x -= 16.,
-            Direction::Up => y += 16.,
-            Direction::Down => y -= 5.,
-            _ => (),
+    /// Walks the tiles between two world positions with Bresenham's line algorithm and returns
+    /// the first tile that blocks the view. The tile the ray starts on never blocks.
+    pub fn raycast(&self, from: Vec3, to: Vec3) -> Option<usize> {
+        let (mut x, mut y) = get_coordinate_from_index(map_idx_f32(from.x, from.y));
+        let (to_x, to_y) = get_coordinate_from_index(map_idx_f32(to.x, to.y));
+        let delta_x = i32::abs(to_x - x);
+        let delta_y = -i32::abs(to_y - y);
+        let step_x = if x < to_x { 1 } else { -1 };
+        let step_y = if y < to_y { 1 } else { -1 };
+        let mut error = delta_x + delta_y;
+
+        while (x, y) != (to_x, to_y) {
+            let doubled_error = 2 * error;
+            let steps_x = doubled_error >= delta_y;
+            let steps_y = doubled_error <= delta_x;
+            // A diagonal step squeezes between the corners of two walls meeting diagonally
+            if steps_x && steps_y {
+                let side_x = self.blocking_view(x + step_x, y);
+                let side_y = self.blocking_view(x, y + step_y);
+                if let (Some(idx), Some(_)) = (side_x, side_y) {
+                    return Some(idx);
+                }
+            }
+            if steps_x {
+                error += delta_y;
+                x += step_x;
+            }
+            if steps_y {
+                error += delta_x;
+                y += step_y;
+            }
+            if let Some(idx) = self.blocking_view(x, y) {
+                return Some(idx);
+            }
+        }
+        None
+    }
+
+    /// The index of the tile if it blocks the view
+    fn blocking_view(&self, x: i32, y: i32) -> Option<usize> {
+        try_map_idx(x, y).filter(|idx| !self.tiles[*idx].is_ground())
+    }
+
+    pub fn can_see(&self, from: Vec3, to: Vec3) -> bool {
+        self.raycast(from, to).is_none()
+    }
+
+    /// The terrain under the position
+    pub fn terrain_at(&self, pos: Vec3) -> Terrain {
+        self.tiles[map_idx_f32(pos.x, pos.y)].terrain()
+    }
+
+    /// The first tile the hitbox of a unit wading this deep can't stand on among all tiles it
+    /// overlaps
+    pub fn blocking_tile(&self, hitbox: &Hitbox, wading: Wading) -> Option<usize> {
+        let (min, max) = tile_span(hitbox);
+        (min.1..=max.1)
+            .flat_map(|y| (min.0..=max.0).map(move |x| (x, y)))
+            .find_map(|(x, y)| self.impassable_tile(x, y, wading))
+    }
+
+    /// Sweeps the hitbox along a horizontal or vertical step through the tile grid.
+    /// Returns how much of the step is free as a fraction together with the tile that stopped
+    /// the hitbox. Every row or column the hitbox passes is checked, so no step is too long to
+    /// tunnel through a wall.
+    pub fn sweep_hitbox(
+        &self,
+        hitbox: &Hitbox,
+        wading: Wading,
+        step: Vec3,
+    ) -> (f32, Option<usize>) {
+        let horizontal = step.x != 0.;
+        let (delta, pos, extent) = if horizontal {
+            (step.x, hitbox.pos.x, hitbox.width)
+        } else {
+            (step.y, hitbox.pos.y, hitbox.height)
+        };
+        if delta == 0. {
+            return (1., None);
+        }
+        let direction = delta.signum();
+        let leading_edge = pos + direction * extent / 2.;
+        let (min, max) = tile_span(hitbox);
+        let crossed = if horizontal {
+            min.1..=max.1
+        } else {
+            min.0..=max.0
+        };
+
+        // Only lines the hitbox newly enters can stop it, so units overlapping a wall a bit can
+        // still walk away from it
+        let first_line =
+            tile_coordinate_f32(leading_edge - direction * EDGE_TOLERANCE) + direction as i32;
+        let last_line = tile_coordinate_f32(leading_edge + delta - direction * EDGE_TOLERANCE);
+        let mut line = first_line;
+        while (last_line - line) * direction as i32 >= 0 {
+            for crossing in crossed.clone() {
+                let (x, y) = if horizontal {
+                    (line, crossing)
+                } else {
+                    (crossing, line)
+                };
+                if let Some(idx) = self.impassable_tile(x, y, wading) {
+                    let near_side =
+                        (line * TILE_SIZE as i32) as f32 - direction * TILE_SIZE as f32 / 2.;
+                    let fraction = ((near_side - leading_edge) / delta).clamp(0., 1.);
+                    return (fraction, Some(idx));
+                }
+            }
+            line += direction as i32;
         }
-        self.tiles[map_idx_f32(x, y)] == TileType::Floor
+        (1., None)
+    }
+
+    /// Tiles that are no ground or too deep to wade through. The outer tiles of the map are
+    /// always walls so there is no need to block outside of it.
+    fn impassable_tile(&self, x: i32, y: i32, wading: Wading) -> Option<usize> {
+        try_map_idx(x, y).filter(|idx| !self.tiles[*idx].is_passable(wading))
     }
 
     pub fn render(&self, commands: &mut Commands, map_textures: Res<MapAssets>) {
+        let exit_idx = map_idx_f32(self.exit_pos.x, self.exit_pos.y);
         for y in 0..MAP_HEIGHT {
             for x in 0..MAP_WIDTH {
                 let idx = map_idx(x, y);
                 match self.tiles[idx] {
+                    TileType::Floor if idx == exit_idx => {
+                        spawn_sprite(commands, map_textures.ladder.clone(), x, y, 0.1)
+                    }
                     TileType::Floor => draw_floor(commands, &map_textures, x, y),
+                    TileType::Mud => draw_terrain(commands, &map_textures, x, y, MUD_COLOR),
+                    TileType::Ice => draw_terrain(commands, &map_textures, x, y, ICE_COLOR),
+                    TileType::Water => draw_terrain(commands, &map_textures, x, y, WATER_COLOR),
                     TileType::Wall => draw_wall(commands, &map_textures, self, x, y),
                     TileType::Void => {
                         /* debugging purpose
                         commands.spawn_bundle(SpriteBundle {
-                            texture: map_textures._ladder.clone(),
+                            texture: map_textures.ladder.clone(),
                             transform: Transform {
                                 translation: Vec3::new(
                                     (x * TILE_SIZE as i32) as f32,
@@ -270,36 +567,108 @@ impl Map {
     }
 }
 
+/// Covers part of some rooms with mud, ice or water. A ring of floor is left along the walls, so
+/// the patches never cut off a corridor or the way through a room.
+fn place_terrain_patches(tiles: &mut [TileType], rooms: &[Room], rng: &mut impl Rng) {
+    for room in rooms {
+        if matches!(room.room_type, RoomType::Start | RoomType::Exit)
+            || !rng.gen_bool(TERRAIN_PATCH_CHANCE)
+        {
+            continue;
+        }
+        let (min, max) = (room.bounds.min(), room.bounds.max());
+        let (inner_width, inner_height) = (max.0 - min.0 - 1, max.1 - min.1 - 1);
+        if inner_width < 1 || inner_height < 1 {
+            continue;
+        }
+        let tile_type = match rng.gen_range(0..3) {
+            0 => TileType::Mud,
+            1 => TileType::Ice,
+            _ => TileType::Water,
+        };
+        let width = rng.gen_range(1..=inner_width);
+        let height = rng.gen_range(1..=inner_height);
+        let x = rng.gen_range(min.0 + 1..=max.0 - width);
+        let y = rng.gen_range(min.1 + 1..=max.1 - height);
+        for patch_y in y..y + height {
+            for patch_x in x..x + width {
+                tiles[map_idx(patch_x, patch_y)] = tile_type;
+            }
+        }
+    }
+}
+
+/// Ranks the rooms by their walking distance from the start and tags them accordingly.
+/// The closest room is the start, the room holding the exit tile is the exit, the hardest of the
+/// remaining rooms holds the boss and one random other room is a treasure room.
+fn classify_rooms(
+    mut rectangles: Vec<Rectangle>,
+    distances: &DijkstraMap,
+    exit_idx: usize,
+    rng: &mut impl Rng,
+) -> Vec<Room> {
+    let (exit_x, exit_y) = get_coordinate_from_index(exit_idx);
+    let exit_rectangle = Rectangle::new(exit_x, exit_y, 1, 1);
+
+    rectangles.sort_by_key(|r| {
+        let (x, y) = r.center();
+        distances.distance(map_idx(x, y)).unwrap_or(i32::MAX)
+    });
+    let mut rooms: Vec<Room> = rectangles
+        .into_iter()
+        .enumerate()
+        .map(|(difficulty, bounds)| Room {
+            room_type: if difficulty == 0 {
+                RoomType::Start
+            } else if bounds.intersects(&exit_rectangle) {
+                RoomType::Exit
+            } else {
+                RoomType::Normal
+            },
+            bounds,
+            difficulty: difficulty as u32,
+        })
+        .collect();
+
+    if let Some(boss_room) = rooms
+        .iter_mut()
+        .rev()
+        .find(|r| r.room_type == RoomType::Normal)
+    {
+        boss_room.room_type = RoomType::Boss;
+    }
+    let normal_rooms = rooms
+        .iter()
+        .filter(|r| r.room_type == RoomType::Normal)
+        .count();
+    if normal_rooms > 0 {
+        let treasure_room = rng.gen_range(0..normal_rooms);
+        if let Some(room) = rooms
+            .iter_mut()
+            .filter(|r| r.room_type == RoomType::Normal)
+            .nth(treasure_room)
+        {
+            room.room_type = RoomType::Treasure;
+        }
+    }
+    rooms
+}
+
 fn set_walls(tiles: &mut [TileType]) {
     let mut wall_indeces: Vec<usize> = Vec::new();
     for (idx, tile_type) in tiles.iter().enumerate() {
         if *tile_type == TileType::Floor {
-            // Go over all 4 neighbour tiles
+            // Go over all 8 neighbour tiles so the corners of rooms, wide corridors and
+            // diagonal staircases are closed as well
             let (x, y) = get_coordinate_from_index(idx);
-            let neighbour_indeces = vec![(x + 1, y), (x - 1, y), (x, y + 1), (x, y - 1)];
-            let mut pushed_indeces: Vec<(i32, i32)> = Vec::new();
-            for (neighbour_x, neighbour_y) in neighbour_indeces {
-                if let Some(index) = try_map_idx(neighbour_x, neighbour_y) {
-                    if tiles[index] == TileType::Void {
-                        wall_indeces.push(map_idx(neighbour_x, neighbour_y));
-                        pushed_indeces.push((neighbour_x, neighbour_y));
-                    }
-                }
-            }
-            // It should never be more than 2 indeces that have been pushed
-            // If this is the case a corner is identified so a special wall has to be inserted between
-            let mut special_x = x;
-            let mut special_y = y;
-            if pushed_indeces.len() == 2 {
-                for (pushed_x, pushed_y) in pushed_indeces {
-                    if pushed_x != x {
-                        special_x = pushed_x
-                    }
-                    if pushed_y != y {
-                        special_y = pushed_y
+            for neighbour_y in y - 1..=y + 1 {
+                for neighbour_x in x - 1..=x + 1 {
+                    if let Some(index) = try_map_idx(neighbour_x, neighbour_y) {
+                        if tiles[index] == TileType::Void {
+                            wall_indeces.push(index);
+                        }
                     }
                 }
-                wall_indeces.push(map_idx(special_x, special_y));
             }
         }
     }
@@ -308,48 +677,139 @@ fn set_walls(tiles: &mut [TileType]) {
     }
 }
 
-fn build_corridors(tiles: &mut [TileType], rooms: &[Rectangle]) {
+fn build_corridors(
+    tiles: &mut [TileType],
+    rooms: &[Rectangle],
+    corridors: &CorridorSettings,
+    rng: &mut impl Rng,
+) {
     let mut rooms = rooms.to_owned();
-    rooms.sort_by(|a, b| a.center().0.cmp(&b.center().0));
+    rooms.sort_by_key(|r| r.center().0);
 
     for (i, room) in rooms.iter().enumerate().skip(1) {
         let prev = rooms[i - 1].center();
         let new = room.center();
 
-        let mut rng = rand::thread_rng();
-        let horizontal_first = rng.gen_range(0..=1) == 1;
-        if horizontal_first {
-            apply_horizontal_tunnel(tiles, prev.0, new.0, prev.1);
-            apply_vertical_tunnel(tiles, prev.1, new.1, new.0);
-        } else {
-            apply_vertical_tunnel(tiles, prev.1, new.1, prev.0);
-            apply_horizontal_tunnel(tiles, prev.0, new.0, new.1);
+        let style = match corridors.style {
+            TunnelStyle::Random => match rng.gen_range(0..3) {
+                0 => TunnelStyle::LShaped,
+                1 => TunnelStyle::Diagonal,
+                _ => TunnelStyle::Winding,
+            },
+            style => style,
+        };
+        match style {
+            TunnelStyle::Diagonal => apply_diagonal_tunnel(tiles, prev, new, corridors.width),
+            TunnelStyle::Winding => apply_winding_tunnel(tiles, prev, new, corridors.width, rng),
+            TunnelStyle::LShaped | TunnelStyle::Random => {
+                let horizontal_first = rng.gen_range(0..=1) == 1;
+                if horizontal_first {
+                    apply_horizontal_tunnel(tiles, prev.0, new.0, prev.1, corridors.width);
+                    apply_vertical_tunnel(tiles, prev.1, new.1, new.0, corridors.width);
+                } else {
+                    apply_vertical_tunnel(tiles, prev.1, new.1, prev.0, corridors.width);
+                    apply_horizontal_tunnel(tiles, prev.0, new.0, new.1, corridors.width);
+                }
+            }
         }
     }
 }
 
-fn apply_vertical_tunnel(tiles: &mut [TileType], y1: i32, y2: i32, x: i32) {
-    for y in min(y1, y2)..=max(y1, y2) {
-        if let Some(idx) = try_map_idx(x, y) {
-            if tiles[idx] == TileType::Floor {
-                continue;
+/// Turns a square of `width` tiles around the given tile into floor.
+/// The outermost tiles of the map are left untouched to keep space for walls.
+fn carve_tunnel_tile(tiles: &mut [TileType], x: i32, y: i32, width: i32) {
+    let start_x = x - (width - 1) / 2;
+    let start_y = y - (width - 1) / 2;
+    for tunnel_y in start_y..start_y + width {
+        for tunnel_x in start_x..start_x + width {
+            if (1..MAP_WIDTH - 1).contains(&tunnel_x) && (1..MAP_HEIGHT - 1).contains(&tunnel_y) {
+                tiles[map_idx(tunnel_x, tunnel_y)] = TileType::Floor;
             }
-            tiles[idx] = TileType::Floor;
         }
     }
 }
 
-fn apply_horizontal_tunnel(tiles: &mut [TileType], x1: i32, x2: i32, y: i32) {
+fn apply_vertical_tunnel(tiles: &mut [TileType], y1: i32, y2: i32, x: i32, width: i32) {
+    for y in min(y1, y2)..=max(y1, y2) {
+        carve_tunnel_tile(tiles, x, y, width);
+    }
+}
+
+fn apply_horizontal_tunnel(tiles: &mut [TileType], x1: i32, x2: i32, y: i32, width: i32) {
     for x in min(x1, x2)..=max(x1, x2) {
-        if let Some(idx) = try_map_idx(x, y) {
-            if tiles[idx] == TileType::Floor {
-                continue;
-            }
-            tiles[idx] = TileType::Floor;
+        carve_tunnel_tile(tiles, x, y, width);
+    }
+}
+
+/// Walks a staircase towards the target that only ever takes horizontal or vertical steps so
+/// units can follow it without squeezing between diagonal walls.
+fn apply_diagonal_tunnel(tiles: &mut [TileType], from: (i32, i32), to: (i32, i32), width: i32) {
+    let total_x = i32::abs(to.0 - from.0);
+    let total_y = i32::abs(to.1 - from.1);
+    let (mut x, mut y) = from;
+    carve_tunnel_tile(tiles, x, y, width);
+    while (x, y) != to {
+        let remaining_x = i32::abs(to.0 - x);
+        let remaining_y = i32::abs(to.1 - y);
+        // Step along the axis that is relatively farther behind
+        if remaining_x * total_y >= remaining_y * total_x && remaining_x > 0 {
+            x += i32::signum(to.0 - x);
+        } else {
+            y += i32::signum(to.1 - y);
         }
+        carve_tunnel_tile(tiles, x, y, width);
     }
 }
 
+/// A random walk that usually steps towards the target but sometimes drifts sideways
+fn apply_winding_tunnel(
+    tiles: &mut [TileType],
+    from: (i32, i32),
+    to: (i32, i32),
+    width: i32,
+    rng: &mut impl Rng,
+) {
+    let (mut x, mut y) = from;
+    carve_tunnel_tile(tiles, x, y, width);
+    while (x, y) != to {
+        let towards_x = i32::signum(to.0 - x);
+        let towards_y = i32::signum(to.1 - y);
+        let (step_x, step_y) = if rng.gen_range(0..4) == 0 {
+            // Drift along the axis that is not needed right now, or randomly if both are
+            match (towards_x, towards_y) {
+                (0, _) => (if rng.gen_bool(0.5) { 1 } else { -1 }, 0),
+                (_, 0) => (0, if rng.gen_bool(0.5) { 1 } else { -1 }),
+                _ if rng.gen_bool(0.5) => (towards_x, 0),
+                _ => (0, towards_y),
+            }
+        } else if towards_x != 0 && (towards_y == 0 || rng.gen_bool(0.5)) {
+            (towards_x, 0)
+        } else {
+            (0, towards_y)
+        };
+        // Never drift onto the map borders which are reserved for walls
+        x = (x + step_x).clamp(1, MAP_WIDTH - 2);
+        y = (y + step_y).clamp(1, MAP_HEIGHT - 2);
+        carve_tunnel_tile(tiles, x, y, width);
+    }
+}
+
+/// The lowest and highest tile coordinates the hitbox overlaps
+pub fn tile_span(hitbox: &Hitbox) -> ((i32, i32), (i32, i32)) {
+    let half_width = hitbox.width / 2. - EDGE_TOLERANCE;
+    let half_height = hitbox.height / 2. - EDGE_TOLERANCE;
+    (
+        (
+            tile_coordinate_f32(hitbox.pos.x - half_width),
+            tile_coordinate_f32(hitbox.pos.y - half_height),
+        ),
+        (
+            tile_coordinate_f32(hitbox.pos.x + half_width),
+            tile_coordinate_f32(hitbox.pos.y + half_height),
+        ),
+    )
+}
+
 fn set_room_tiles(tiles: &mut [TileType], room: &Rectangle) {
     for y in room.min().1..=room.max().1 {
         for x in room.min().0..=room.max().0 {
@@ -358,8 +818,7 @@ fn set_room_tiles(tiles: &mut [TileType], room: &Rectangle) {
     }
 }
 
-fn generate_random_rectangle() -> Rectangle {
-    let mut rng = rand::thread_rng();
+fn generate_random_rectangle(rng: &mut impl Rng) -> Rectangle {
     // Always keep space for walls that appear next to the floor bounds of a room
     let x = rng.gen_range(1..MAP_WIDTH - 1 - MAX_ROOM_WIDTH as i32);
     let y = rng.gen_range(1..MAP_HEIGHT - 1 - MAX_ROOM_HEIGHT as i32);
@@ -406,6 +865,30 @@ fn draw_floor(commands: &mut Commands, map_textures: &Res<MapAssets>, x: i32, y:
     });
 }
 
+/// There are no sprites for the terrain yet, so it is a tinted layer over the floor
+fn draw_terrain(
+    commands: &mut Commands,
+    map_textures: &Res<MapAssets>,
+    x: i32,
+    y: i32,
+    color: Color,
+) {
+    draw_floor(commands, map_textures, x, y);
+    commands.spawn_bundle(SpriteBundle {
+        sprite: Sprite {
+            color,
+            custom_size: Some(Vec2::splat(TILE_SIZE as f32)),
+            ..default()
+        },
+        transform: Transform::from_xyz(
+            (x * TILE_SIZE as i32) as f32,
+            (y * TILE_SIZE as i32) as f32,
+            0.15,
+        ),
+        ..default()
+    });
+}
+
 fn generate_random_index(max: i32) -> i32 {
     let mut rng = rand::thread_rng();
     rng.gen_range(0..max)
@@ -662,3 +1145,248 @@ fn should_be_false_when_rectangle_center_not_too_close_to_bounds() {
 
     assert!(rectangle1.center_x_units_away_from_bounds(&rectangle2, units));
 }
+
+#[cfg(test)]
+pub fn map_with_floor(floor: &Rectangle) -> Map {
+    let mut tiles = vec![TileType::Wall; NUM_TILES];
+    set_room_tiles(&mut tiles, floor);
+    Map {
+        tiles,
+        rooms: vec![Room {
+            bounds: floor.clone(),
+            room_type: RoomType::Start,
+            difficulty: 0,
+        }],
+        player_start_pos: Vec2::ZERO,
+        exit_pos: Vec2::ZERO,
+    }
+}
+
+#[cfg(test)]
+fn tile_center(x: i32, y: i32) -> Vec3 {
+    Vec3::new(
+        (x * TILE_SIZE as i32) as f32,
+        (y * TILE_SIZE as i32) as f32,
+        0.,
+    )
+}
+
+#[test]
+fn should_see_across_open_room() {
+    let map = map_with_floor(&Rectangle::new(2, 2, 8, 8));
+
+    assert!(map.can_see(tile_center(2, 2), tile_center(9, 9)));
+    assert!(map.can_see(tile_center(3, 8), tile_center(9, 2)));
+    assert!(map.can_see(tile_center(5, 5), tile_center(5, 5)));
+}
+
+#[test]
+fn should_return_first_blocking_tile() {
+    let mut map = map_with_floor(&Rectangle::new(2, 2, 8, 8));
+    map.tiles[map_idx(5, 4)] = TileType::Wall;
+    map.tiles[map_idx(7, 4)] = TileType::Wall;
+
+    assert_eq!(
+        map.raycast(tile_center(2, 4), tile_center(9, 4)),
+        Some(map_idx(5, 4))
+    );
+    assert_eq!(
+        map.raycast(tile_center(9, 4), tile_center(2, 4)),
+        Some(map_idx(7, 4))
+    );
+    assert!(!map.can_see(tile_center(2, 4), tile_center(9, 4)));
+    assert!(map.can_see(tile_center(2, 5), tile_center(9, 5)));
+}
+
+#[test]
+fn should_not_see_between_walls_meeting_diagonally() {
+    let mut map = map_with_floor(&Rectangle::new(2, 2, 8, 8));
+    map.tiles[map_idx(5, 4)] = TileType::Wall;
+    map.tiles[map_idx(4, 5)] = TileType::Wall;
+
+    assert!(!map.can_see(tile_center(4, 4), tile_center(5, 5)));
+    assert!(!map.can_see(tile_center(6, 6), tile_center(3, 3)));
+    // A single corner doesn't block a diagonal view
+    map.tiles[map_idx(4, 5)] = TileType::Floor;
+    assert!(map.can_see(tile_center(4, 4), tile_center(5, 5)));
+}
+
+#[test]
+fn should_rank_rooms_by_distance_from_start() {
+    let rectangles = vec![
+        Rectangle::new(40, 2, 5, 5),
+        Rectangle::new(2, 2, 5, 5),
+        Rectangle::new(20, 2, 5, 5),
+        Rectangle::new(60, 2, 5, 5),
+    ];
+    let mut tiles = vec![TileType::Void; NUM_TILES];
+    for rectangle in &rectangles {
+        set_room_tiles(&mut tiles, rectangle);
+    }
+    apply_horizontal_tunnel(&mut tiles, 4, 62, 4, 1);
+    let distances = DijkstraMap::new(&tiles, &[map_idx(4, 4)]);
+
+    let rooms = classify_rooms(
+        rectangles,
+        &distances,
+        map_idx(64, 6),
+        &mut rand::thread_rng(),
+    );
+
+    let room_types: Vec<RoomType> = rooms.iter().map(|r| r.room_type).collect();
+    assert_eq!(
+        room_types,
+        vec![
+            RoomType::Start,
+            RoomType::Treasure,
+            RoomType::Boss,
+            RoomType::Exit
+        ]
+    );
+    let difficulties: Vec<u32> = rooms.iter().map(|r| r.difficulty).collect();
+    assert_eq!(difficulties, vec![0, 1, 2, 3]);
+    assert_eq!(rooms[0].bounds.center(), (4, 4));
+}
+
+#[test]
+fn should_carve_corridors_as_wide_as_configured() {
+    let mut tiles = vec![TileType::Void; NUM_TILES];
+    apply_horizontal_tunnel(&mut tiles, 10, 20, 10, 3);
+
+    for y in 9..=11 {
+        assert_eq!(tiles[map_idx(15, y)], TileType::Floor);
+    }
+    assert_eq!(tiles[map_idx(15, 8)], TileType::Void);
+    assert_eq!(tiles[map_idx(15, 12)], TileType::Void);
+}
+
+#[test]
+fn should_connect_rooms_through_diagonal_and_winding_tunnels() {
+    for width in 1..=3 {
+        let mut tiles = vec![TileType::Void; NUM_TILES];
+        apply_diagonal_tunnel(&mut tiles, (10, 10), (30, 17), width);
+        apply_winding_tunnel(
+            &mut tiles,
+            (30, 17),
+            (50, 40),
+            width,
+            &mut rand::thread_rng(),
+        );
+        set_walls(&mut tiles);
+
+        let distances = DijkstraMap::new(&tiles, &[map_idx(10, 10)]);
+        assert!(distances.distance(map_idx(30, 17)).is_some());
+        assert!(distances.distance(map_idx(50, 40)).is_some());
+    }
+}
+
+#[test]
+fn should_generate_same_map_from_same_seed() {
+    let first = Map::new(CorridorSettings::default(), &mut StdRng::seed_from_u64(7));
+    let second = Map::new(CorridorSettings::default(), &mut StdRng::seed_from_u64(7));
+
+    assert!(first.tiles == second.tiles);
+    assert_eq!(first.player_start_pos, second.player_start_pos);
+    assert_eq!(first.exit_pos, second.exit_pos);
+}
+
+#[test]
+fn should_find_wall_under_any_edge_of_hitbox() {
+    let map = map_with_floor(&Rectangle::new(2, 2, 4, 4));
+    let hitbox_at = |pos: Vec3| Hitbox {
+        pos,
+        width: 32.,
+        height: 42.,
+    };
+
+    assert_eq!(
+        map.blocking_tile(&hitbox_at(tile_center(3, 3)), Wading::default()),
+        None
+    );
+    // The hitbox reaches 5 pixels above and below its tile
+    assert_eq!(
+        map.blocking_tile(&hitbox_at(tile_center(3, 2)), Wading::default()),
+        Some(map_idx(3, 1))
+    );
+    // Walls to the left block just like any other side
+    assert_eq!(
+        map.blocking_tile(&hitbox_at(tile_center(2, 3) - Vec3::X), Wading::default()),
+        Some(map_idx(1, 2))
+    );
+}
+
+#[test]
+fn should_stop_sweep_in_front_of_wall() {
+    let map = map_with_floor(&Rectangle::new(2, 2, 4, 4));
+    let hitbox = Hitbox {
+        pos: tile_center(3, 3),
+        width: 30.,
+        height: 30.,
+    };
+
+    // The wall column 6 starts 65 pixels right of the hitbox
+    let (fraction, tile) = map.sweep_hitbox(&hitbox, Wading::default(), Vec3::new(100., 0., 0.));
+    assert_eq!(tile, Some(map_idx(6, 3)));
+    assert!((fraction * 100. - 65.).abs() < 0.001);
+    assert_eq!(
+        map.sweep_hitbox(&hitbox, Wading::default(), Vec3::new(0., -10., 0.)),
+        (1., None)
+    );
+}
+
+#[test]
+fn should_not_tunnel_through_thin_wall() {
+    let mut map = map_with_floor(&Rectangle::new(2, 2, 10, 4));
+    map.tiles[map_idx(5, 3)] = TileType::Wall;
+    let hitbox = Hitbox {
+        pos: tile_center(3, 3),
+        width: 16.,
+        height: 16.,
+    };
+
+    let (fraction, tile) = map.sweep_hitbox(&hitbox, Wading::default(), Vec3::new(200., 0., 0.));
+    assert_eq!(tile, Some(map_idx(5, 3)));
+    assert!((fraction * 200. - 40.).abs() < 0.001);
+    // Moving back out of the way is not blocked by the wall
+    assert_eq!(
+        map.sweep_hitbox(&hitbox, Wading::default(), Vec3::new(-20., 0., 0.)),
+        (1., None)
+    );
+}
+
+#[test]
+fn should_only_let_units_wading_deep_enough_through_water() {
+    use crate::{
+        enemy::components::EnemyKind,
+        player::components::{PLAYER_FEET, PLAYER_WADING},
+    };
+
+    let mut map = map_with_floor(&Rectangle::new(2, 2, 8, 8));
+    map.tiles[map_idx(5, 4)] = TileType::Water;
+    let crossing = |size: Vec2, wading: Wading| {
+        let hitbox = Hitbox {
+            pos: tile_center(3, 4),
+            width: size.x,
+            height: size.y,
+        };
+        map.sweep_hitbox(&hitbox, wading, Vec3::new(100., 0., 0.))
+    };
+
+    assert_eq!(
+        crossing(PLAYER_FEET.size.unwrap(), PLAYER_WADING),
+        (1., None)
+    );
+    assert_eq!(
+        crossing(
+            EnemyKind::BigDemon.hitbox_size(),
+            EnemyKind::BigDemon.wading()
+        ),
+        (1., None)
+    );
+    let (fraction, tile) = crossing(
+        EnemyKind::BigZombie.hitbox_size(),
+        EnemyKind::BigZombie.wading(),
+    );
+    assert_eq!(tile, Some(map_idx(5, 4)));
+    assert!((fraction - 0.33).abs() < 0.001);
+}
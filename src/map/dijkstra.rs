@@ -0,0 +1,154 @@
+use std::collections::VecDeque;
+
+use crate::NUM_TILES;
+
+#[cfg(test)]
+use super::components::map_idx;
+use super::components::{get_coordinate_from_index, try_map_idx, TileType, Wading};
+
+/// Walking distance of every floor tile to the closest of one or more source tiles.
+/// Unreachable tiles and those too deep to wade through have no distance.
+/// Units walk "downhill" to approach the sources and "uphill" to get away from them.
+#[derive(Clone, Debug)]
+pub struct DijkstraMap {
+    distances: Vec<Option<i32>>,
+    wading: Wading,
+}
+
+impl DijkstraMap {
+    /// Leads through all ground, however deep
+    pub fn new(tiles: &[TileType], sources: &[usize]) -> Self {
+        Self::wading(tiles, sources, Wading(f32::INFINITY))
+    }
+
+    /// Only leads through ground units wading this deep can enter. A source in deeper water
+    /// still counts, so its distances start at the shore.
+    pub fn wading(tiles: &[TileType], sources: &[usize], wading: Wading) -> Self {
+        let mut dijkstra_map = Self {
+            distances: vec![None; NUM_TILES],
+            wading,
+        };
+        dijkstra_map.update(tiles, sources);
+        dijkstra_map
+    }
+
+    /// Recalculates all distances in place so the map can be refreshed whenever a source moves
+    /// without allocating again.
+    pub fn update(&mut self, tiles: &[TileType], sources: &[usize]) {
+        self.distances.iter_mut().for_each(|d| *d = None);
+        let mut open_list = VecDeque::new();
+        for &source in sources {
+            if tiles[source].is_ground() && self.distances[source].is_none() {
+                self.distances[source] = Some(0);
+                open_list.push_back(source);
+            }
+        }
+
+        // Every step costs the same so a breadth first search already yields the shortest distances
+        while let Some(idx) = open_list.pop_front() {
+            let next_distance = self.distances[idx].unwrap() + 1;
+            for neighbour in neighbours(idx) {
+                if tiles[neighbour].is_passable(self.wading) && self.distances[neighbour].is_none()
+                {
+                    self.distances[neighbour] = Some(next_distance);
+                    open_list.push_back(neighbour);
+                }
+            }
+        }
+    }
+
+    pub fn distance(&self, idx: usize) -> Option<i32> {
+        self.distances.get(idx).copied().flatten()
+    }
+
+    /// The neighbouring tile that leads closest to a source
+    pub fn downhill(&self, idx: usize) -> Option<usize> {
+        let current = self.distance(idx)?;
+        neighbours(idx)
+            .filter_map(|n| self.distance(n).map(|d| (n, d)))
+            .filter(|(_, d)| *d < current)
+            .min_by_key(|(_, d)| *d)
+            .map(|(n, _)| n)
+    }
+
+    /// The neighbouring tile that leads farthest away from all sources
+    pub fn uphill(&self, idx: usize) -> Option<usize> {
+        let current = self.distance(idx)?;
+        neighbours(idx)
+            .filter_map(|n| self.distance(n).map(|d| (n, d)))
+            .filter(|(_, d)| *d > current)
+            .max_by_key(|(_, d)| *d)
+            .map(|(n, _)| n)
+    }
+
+    /// The reachable tile with the longest walk from any source
+    pub fn farthest(&self) -> Option<usize> {
+        self.distances
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, d)| d.map(|d| (idx, d)))
+            .max_by_key(|(_, d)| *d)
+            .map(|(idx, _)| idx)
+    }
+}
+
+fn neighbours(idx: usize) -> impl Iterator<Item = usize> {
+    let (x, y) = get_coordinate_from_index(idx);
+    [(x + 1, y), (x - 1, y), (x, y + 1), (x, y - 1)]
+        .into_iter()
+        .filter_map(|(x, y)| try_map_idx(x, y))
+}
+
+#[cfg(test)]
+fn corridor_tiles(from: (i32, i32), to_x: i32) -> Vec<TileType> {
+    let mut tiles = vec![TileType::Wall; NUM_TILES];
+    for x in from.0..=to_x {
+        tiles[map_idx(x, from.1)] = TileType::Floor;
+    }
+    tiles
+}
+
+#[test]
+fn should_count_steps_along_corridor() {
+    let tiles = corridor_tiles((2, 3), 7);
+    let dijkstra_map = DijkstraMap::new(&tiles, &[map_idx(2, 3)]);
+
+    assert_eq!(dijkstra_map.distance(map_idx(2, 3)), Some(0));
+    assert_eq!(dijkstra_map.distance(map_idx(7, 3)), Some(5));
+    assert_eq!(dijkstra_map.distance(map_idx(7, 4)), None);
+    assert_eq!(dijkstra_map.farthest(), Some(map_idx(7, 3)));
+}
+
+#[test]
+fn should_walk_downhill_towards_and_uphill_away_from_source() {
+    let tiles = corridor_tiles((2, 3), 7);
+    let dijkstra_map = DijkstraMap::new(&tiles, &[map_idx(2, 3)]);
+
+    assert_eq!(dijkstra_map.downhill(map_idx(5, 3)), Some(map_idx(4, 3)));
+    assert_eq!(dijkstra_map.uphill(map_idx(5, 3)), Some(map_idx(6, 3)));
+    assert_eq!(dijkstra_map.downhill(map_idx(2, 3)), None);
+    assert_eq!(dijkstra_map.uphill(map_idx(7, 3)), None);
+}
+
+#[test]
+fn should_use_closest_of_multiple_sources() {
+    let tiles = corridor_tiles((2, 3), 12);
+    let dijkstra_map = DijkstraMap::new(&tiles, &[map_idx(2, 3), map_idx(12, 3)]);
+
+    assert_eq!(dijkstra_map.distance(map_idx(4, 3)), Some(2));
+    assert_eq!(dijkstra_map.distance(map_idx(10, 3)), Some(2));
+    assert_eq!(dijkstra_map.farthest(), Some(map_idx(7, 3)));
+}
+
+#[test]
+fn should_only_lead_through_water_units_can_wade() {
+    let mut tiles = corridor_tiles((2, 3), 7);
+    tiles[map_idx(4, 3)] = TileType::Water;
+
+    let dry = DijkstraMap::wading(&tiles, &[map_idx(2, 3)], Wading::default());
+    assert_eq!(dry.distance(map_idx(3, 3)), Some(1));
+    assert_eq!(dry.distance(map_idx(4, 3)), None);
+    assert_eq!(dry.distance(map_idx(7, 3)), None);
+    let wet = DijkstraMap::wading(&tiles, &[map_idx(2, 3)], Wading(32.));
+    assert_eq!(wet.distance(map_idx(7, 3)), Some(5));
+}
@@ -1,17 +1,42 @@
 pub mod components;
+pub mod dijkstra;
 pub mod systems;
 
-use self::{components::Map, systems::*};
-use crate::{movement::components::BlocksMovement, GameState};
+use self::{
+    components::{CorridorSettings, DungeonDepth, Map, PlayerDistanceMap},
+    systems::*,
+};
+use crate::{
+    events::RuledEventAppExt,
+    movement::components::{MoveAttempt, MoveRule},
+    simulation::{SimulationAppExt, SimulationRng},
+    GameState,
+};
 use bevy::prelude::*;
 
 pub struct MapPlugin;
 
 impl Plugin for MapPlugin {
     fn build(&self, app: &mut App) {
-        app.insert_resource(Map::new())
+        let map = Map::new(
+            CorridorSettings::default(),
+            &mut SimulationRng::get_or_seed(&mut app.world).0,
+        );
+        app.insert_resource(DungeonDepth(1))
+            .insert_resource(PlayerDistanceMap::new(&map))
+            .insert_resource(map)
             .add_system_set(SystemSet::on_enter(GameState::AssetsDone).with_system(render_map))
-            .add_system(check_wall_collision.label(BlocksMovement))
-            .add_system(check_room_boundaries.label(BlocksMovement));
+            .add_event_rule::<MoveAttempt, _>(slow_on_terrain.label(MoveRule::Terrain))
+            .add_event_rule::<MoveAttempt, _>(
+                check_wall_collision
+                    .label(MoveRule::Walls)
+                    .after(MoveRule::Terrain),
+            )
+            .add_event_rule::<MoveAttempt, _>(
+                check_room_boundaries
+                    .label(MoveRule::RoomBounds)
+                    .after(MoveRule::Walls),
+            )
+            .add_simulation_system(update_player_distance_map);
     }
 }
@@ -1,13 +1,13 @@
+use crate::collision::components::{Hitbox, MovementColliders};
+use crate::events::{RuledEvent, RuledEventQueue};
 use crate::map::components::Map;
-use crate::movement::components::MoveAttempt;
-use crate::{events::RuledEventQueue, global_components::Rectangular};
-use crate::{GameState, TILE_SIZE};
+use crate::movement::components::{sample_free_fraction, MoveAttempt, MoveRejection};
+use crate::player::components::Player;
+use crate::GameState;
 
 use bevy::prelude::*;
 
-use super::components::{
-    get_coordinate_from_index, map_idx_f32, try_map_idx, MapAssets, Rectangle, RoomBound,
-};
+use super::components::{map_idx_f32, MapAssets, PlayerDistanceMap, RoomBound, Wading};
 
 pub fn render_map(
     mut commands: Commands,
@@ -19,10 +19,56 @@ pub fn render_map(
     game_state.set(GameState::MapDrawn).unwrap();
 }
 
-pub fn check_wall_collision(mut move_events: ResMut<RuledEventQueue<MoveAttempt>>, map: Res<Map>) {
+/// Sticky or deep ground shortens the moves of the units standing on it
+pub fn slow_on_terrain(
+    mut move_events: ResMut<RuledEventQueue<MoveAttempt>>,
+    map: Res<Map>,
+    movement_colliders: MovementColliders,
+) {
+    for move_attempt in move_events.read_events() {
+        // Units feel the ground under their feet
+        let feet = movement_colliders
+            .hitbox_at(move_attempt.entity, move_attempt.origin)
+            .map_or(move_attempt.origin, |hitbox| hitbox.pos);
+        move_attempt.scale(map.terrain_at(feet).speed);
+    }
+}
+
+pub fn check_wall_collision(
+    mut move_events: ResMut<RuledEventQueue<MoveAttempt>>,
+    map: Res<Map>,
+    movement_colliders: MovementColliders,
+    wading_units: Query<&Wading>,
+) {
     for move_attempt in move_events.read_events() {
-        if !map.can_enter_tile_f32(move_attempt.destination, move_attempt.direction) {
-            move_attempt.viable = false;
+        let wading = wading_units
+            .get(move_attempt.entity)
+            .copied()
+            .unwrap_or_default();
+        // Units without a movement collider collide with their center only
+        let hitbox = movement_colliders
+            .hitbox_at(move_attempt.entity, move_attempt.origin)
+            .unwrap_or(Hitbox {
+                pos: move_attempt.origin,
+                width: 0.,
+                height: 0.,
+            });
+        let offset = hitbox.pos - move_attempt.origin;
+        let mut blocking_tile = None;
+        let moved = move_attempt.resolve_per_axis(|position, step| {
+            let (fraction, tile) = map.sweep_hitbox(
+                &Hitbox {
+                    pos: position + offset,
+                    ..hitbox.clone()
+                },
+                wading,
+                step,
+            );
+            blocking_tile = blocking_tile.or(tile);
+            fraction
+        });
+        if let (false, Some(tile)) = (moved, blocking_tile) {
+            move_attempt.reject("check_wall_collision", MoveRejection::Wall { tile });
         }
     }
 }
@@ -33,10 +79,30 @@ pub fn check_room_boundaries(
     room_bound_units: Query<&RoomBound>,
 ) {
     for move_attempt in move_events.read_events() {
-        if let Ok(_) = room_bound_units.get(move_attempt.entity) {
-            if !map.within_room(move_attempt.destination) {
-                move_attempt.viable = false;
-            }
+        if room_bound_units.get(move_attempt.entity).is_ok()
+            && !map.within_room(move_attempt.destination)
+            && !move_attempt.resolve_per_axis(sample_free_fraction(|destination| {
+                map.within_room(destination)
+            }))
+        {
+            move_attempt.reject("check_room_boundaries", MoveRejection::OutsideRoom);
+        }
+    }
+}
+
+/// Only recalculates the distances when the player entered another tile
+pub fn update_player_distance_map(
+    map: Res<Map>,
+    mut player_distance_map: ResMut<PlayerDistanceMap>,
+    player_query: Query<&Transform, (With<Player>, Changed<Transform>)>,
+) {
+    for transform in player_query.iter() {
+        let player_idx = map_idx_f32(transform.translation.x, transform.translation.y);
+        if player_idx != player_distance_map.player_idx {
+            player_distance_map.player_idx = player_idx;
+            player_distance_map
+                .distances
+                .update(&map.tiles, &[player_idx]);
         }
     }
 }
@@ -1,37 +1,349 @@
-use crate::{events::RuledEvent, global_components::Direction};
+use crate::{
+    events::{Rejection, RuledEvent},
+    map::components::Terrain,
+};
 use bevy::prelude::*;
 
+#[cfg(test)]
+use crate::events::RuledEventQueue;
+
+/// Producers of movement intents run before this label, the velocities are integrated after it
+#[derive(Clone, Debug, Hash, PartialEq, Eq, SystemLabel)]
+pub struct IntegrateMovement;
+
+/// Units whose move was rejected lose their velocity at this label in the post update stage,
+/// systems reacting to being stopped run after it
+#[derive(Clone, Debug, Hash, PartialEq, Eq, SystemLabel)]
+pub struct StopRejectedMoves;
+
+/// The rules validating move attempts, in the order they run
 #[derive(Clone, Debug, Hash, PartialEq, Eq, SystemLabel)]
-pub struct BlocksMovement;
+pub enum MoveRule {
+    Terrain,
+    Walls,
+    RoomBounds,
+    Hitboxes,
+}
+
+/// Velocity in pixels per second
+#[derive(Component, Default, Deref, DerefMut)]
+pub struct Velocity(pub Vec2);
+
+/// The direction a unit wants to move in, set by input or AI every tick. A length of 1 is full
+/// speed, longer intents are cut down so diagonal moves are not faster than straight ones.
+#[derive(Component, Default, Deref, DerefMut)]
+pub struct MoveIntent(pub Vec2);
+
+/// How fast a unit can move and how quickly it gets there and stops again.
+/// Acceleration and friction are given in pixels per second squared.
+#[derive(Component, Clone)]
+pub struct MovementStats {
+    pub max_speed: f32,
+    pub acceleration: f32,
+    pub friction: f32,
+}
+
+impl MovementStats {
+    /// Scales acceleration and friction by the traction of the ground. Slow ground doesn't lower
+    /// the max speed, a move rule shortens the moves on it with [`MoveAttempt::scale`].
+    pub fn on_terrain(&self, terrain: Terrain) -> Self {
+        Self {
+            max_speed: self.max_speed,
+            acceleration: self.acceleration * terrain.traction,
+            friction: self.friction * terrain.traction,
+        }
+    }
+
+    /// Speeds up towards the intended direction or slows down by friction without an intent
+    pub fn accelerate(&self, velocity: Vec2, intent: Vec2, delta_seconds: f32) -> Vec2 {
+        let intent = intent.clamp_length_max(1.);
+        if intent == Vec2::ZERO {
+            let speed = velocity.length();
+            let slowed_speed = (speed - self.friction * delta_seconds).max(0.);
+            return velocity.normalize_or_zero() * slowed_speed;
+        }
+        let desired_velocity = intent * self.max_speed;
+        let change = desired_velocity - velocity;
+        let max_change = self.acceleration * delta_seconds;
+        if change.length() <= max_change {
+            desired_velocity
+        } else {
+            velocity + change.normalize() * max_change
+        }
+    }
+}
+
+/// How hard a unit is to push out of the way of others, units without one have a mass of 1.
+/// An infinite mass makes a unit immovable.
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
+pub struct Mass(pub f32);
 
+impl Mass {
+    /// The parts of a separation two overlapping units of these masses each have to move
+    pub fn push_shares(self, other: Mass) -> (f32, f32) {
+        match (self.0.is_infinite(), other.0.is_infinite()) {
+            (true, true) => (0., 0.),
+            (true, false) => (0., 1.),
+            (false, true) => (1., 0.),
+            (false, false) => {
+                let share = other.0 / (self.0 + other.0);
+                (share, 1. - share)
+            }
+        }
+    }
+
+    /// How much an impulse changes the velocity of a unit of this mass
+    pub fn velocity_change(self, impulse: Vec2) -> Vec2 {
+        if self.0.is_infinite() {
+            Vec2::ZERO
+        } else {
+            impulse / self.0
+        }
+    }
+}
+
+/// Pushes a unit from outside, for example a hit or a trap. Friction lets the push die down again
+/// and for `control_loss` seconds the unit ignores its move intent.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Impulse {
+    pub entity: Entity,
+    pub impulse: Vec2,
+    pub control_loss: f32,
+}
+
+/// A pushed unit that can't move on its own until the timer finished
 #[derive(Component)]
-pub struct MovingRandomly {
-    pub timer: Timer,
-    pub speed: f32,
-    pub current_direction: Direction,
-    pub step_counter: i32,
+pub struct Knockback(pub Timer);
+
+impl Default for Mass {
+    fn default() -> Self {
+        Self(1.)
+    }
+}
+
+/// Roams around without a goal, steering clear of walls and other units
+#[derive(Component, Default)]
+pub struct Wander {
+    /// The direction the unit wanders in as an angle
+    pub angle: f32,
+}
+
+impl Wander {
+    pub fn direction(&self) -> Vec2 {
+        Vec2::new(self.angle.cos(), self.angle.sin())
+    }
 }
 
+#[derive(Clone, Debug, PartialEq)]
+pub enum MoveRejection {
+    /// The destination lies on a tile the unit can't stand on
+    Wall { tile: usize },
+    /// A room bound unit tried to leave its room
+    OutsideRoom,
+    /// The destination overlaps the hitbox of another entity
+    Hitbox { other: Entity },
+}
+
+/// How many positions along a blocked move are tried to find the last free one
+const CLAMP_STEPS: i32 = 8;
+
+/// Rules may veto a move attempt or adjust its destination. The consumer applies whatever
+/// destination is left after all rules ran.
+/// Every entity has at most one attempt per frame: further moves of the same entity are added to
+/// its step, so rules always validate the combined move from the actual position.
+#[derive(Debug)]
 pub struct MoveAttempt {
     pub entity: Entity,
+    pub origin: Vec3,
     pub destination: Vec3,
-    pub direction: Direction,
-    pub viable: bool,
+    /// The step before any rule adjusted the destination
+    pub requested_step: Vec3,
+    pub rejections: Vec<Rejection<MoveRejection>>,
+    /// A rule changed the destination since the rules last checked the attempt
+    adjusted: bool,
+    /// The strongest slow down applied with [`MoveAttempt::scale`]
+    speed_factor: f32,
 }
 
 impl MoveAttempt {
-    pub fn new(entity: Entity, destination: Vec3, direction: Direction) -> Self {
+    pub fn new(entity: Entity, origin: Vec3, destination: Vec3) -> Self {
         Self {
             entity,
+            origin,
             destination,
-            direction,
-            viable: true,
+            requested_step: destination - origin,
+            rejections: Vec::new(),
+            adjusted: false,
+            speed_factor: 1.,
         }
     }
+
+    pub fn step(&self) -> Vec3 {
+        self.destination - self.origin
+    }
+
+    /// Shortens the step to a part of its length for a slow effect. Of several slow effects the
+    /// strongest one wins, so a rule that runs again doesn't slow the unit down twice.
+    pub fn scale(&mut self, factor: f32) {
+        if factor >= self.speed_factor {
+            return;
+        }
+        self.destination = self.origin + self.step() * (factor / self.speed_factor);
+        self.speed_factor = factor;
+        self.adjusted = true;
+    }
+
+    /// The requested step after slow effects, which is what a move without obstacles covers
+    pub fn slowed_step(&self) -> Vec3 {
+        self.requested_step * self.speed_factor
+    }
+
+    /// Moves horizontally first and vertically from wherever that ended. `free_fraction` tells
+    /// how much of a horizontal or vertical step from a position is free, so each part is
+    /// shortened in front of obstacles and units slide along them when moving diagonally.
+    /// Returns false if the unit can't move at all.
+    pub fn resolve_per_axis(&mut self, mut free_fraction: impl FnMut(Vec3, Vec3) -> f32) -> bool {
+        let mut position = self.origin;
+        let mut shortened = false;
+        for axis in [Vec3::X, Vec3::Y] {
+            let step = self.step() * axis;
+            if step != Vec3::ZERO {
+                let fraction = free_fraction(position, step);
+                shortened |= fraction < 1.;
+                position += step * fraction;
+            }
+        }
+        // A free move keeps its exact destination instead of the sum of its parts
+        if shortened && position != self.destination {
+            self.destination = position;
+            self.adjusted = true;
+        }
+        self.destination != self.origin
+    }
+}
+
+/// Turns a check of single positions into a `free_fraction` for
+/// [`MoveAttempt::resolve_per_axis`] by trying positions along the step
+pub fn sample_free_fraction(is_free: impl Fn(Vec3) -> bool) -> impl Fn(Vec3, Vec3) -> f32 {
+    move |position, step| {
+        (1..=CLAMP_STEPS)
+            .rev()
+            .map(|i| i as f32 / CLAMP_STEPS as f32)
+            .find(|fraction| is_free(position + step * *fraction))
+            .unwrap_or(0.)
+    }
 }
 
 impl RuledEvent for MoveAttempt {
-    fn is_viable(&self) -> bool {
-        self.viable
+    type Reason = MoveRejection;
+
+    fn rejections(&self) -> &[Rejection<Self::Reason>] {
+        &self.rejections
+    }
+
+    fn reject(&mut self, rule: &'static str, reason: Self::Reason) {
+        self.rejections.push(Rejection { rule, reason });
+    }
+
+    fn merge(&mut self, other: &Self) -> bool {
+        if other.entity != self.entity {
+            return false;
+        }
+        self.destination += other.step();
+        self.requested_step += other.requested_step;
+        true
     }
+
+    fn take_adjusted(&mut self) -> bool {
+        std::mem::take(&mut self.adjusted)
+    }
+}
+
+#[test]
+fn should_scale_step_by_strongest_slow_effect() {
+    let mut move_attempt = MoveAttempt::new(Entity::from_raw(0), Vec3::ZERO, Vec3::new(8., 4., 0.));
+
+    move_attempt.scale(0.5);
+    assert_eq!(move_attempt.destination, Vec3::new(4., 2., 0.));
+    move_attempt.scale(0.5);
+    move_attempt.scale(0.75);
+    assert_eq!(move_attempt.destination, Vec3::new(4., 2., 0.));
+    move_attempt.scale(0.25);
+    assert_eq!(move_attempt.destination, Vec3::new(2., 1., 0.));
+    assert_eq!(move_attempt.requested_step, Vec3::new(8., 4., 0.));
+    assert_eq!(move_attempt.slowed_step(), Vec3::new(2., 1., 0.));
+}
+
+#[test]
+fn should_slide_along_wall_with_free_axis_of_move() {
+    let mut move_attempt = MoveAttempt::new(Entity::from_raw(0), Vec3::ZERO, Vec3::new(2., 2., 0.));
+    // Everything above the origin is blocked
+    let is_free = |destination: Vec3| destination.y <= 0.;
+
+    assert!(move_attempt.resolve_per_axis(sample_free_fraction(is_free)));
+    assert_eq!(move_attempt.destination, Vec3::new(2., 0., 0.));
+}
+
+#[test]
+fn should_clamp_each_axis_to_last_free_position() {
+    let mut move_attempt = MoveAttempt::new(Entity::from_raw(0), Vec3::ZERO, Vec3::new(8., 8., 0.));
+
+    assert!(
+        move_attempt.resolve_per_axis(sample_free_fraction(|destination| {
+            destination.x <= 5. && destination.y <= 3.
+        }))
+    );
+    assert_eq!(move_attempt.destination, Vec3::new(5., 3., 0.));
+    assert!(
+        !move_attempt.resolve_per_axis(sample_free_fraction(|destination| {
+            destination == Vec3::ZERO
+        }))
+    );
+    assert_eq!(move_attempt.destination, Vec3::ZERO);
+}
+
+#[test]
+fn should_combine_moves_of_same_entity() {
+    let mut queue = RuledEventQueue::new();
+    let entity = Entity::from_raw(0);
+    queue.add_event(MoveAttempt::new(entity, Vec3::ZERO, Vec3::new(2., 0., 0.)));
+    queue.add_event(MoveAttempt::new(entity, Vec3::ZERO, Vec3::new(0., 3., 0.)));
+    queue.add_event(MoveAttempt::new(Entity::from_raw(1), Vec3::ZERO, Vec3::X));
+
+    let moves: Vec<MoveAttempt> = queue.consume_events().collect();
+    assert_eq!(moves.len(), 2);
+    assert_eq!(moves[0].destination, Vec3::new(2., 3., 0.));
+    assert_eq!(moves[0].requested_step, Vec3::new(2., 3., 0.));
+}
+
+#[test]
+fn should_not_move_faster_diagonally() {
+    let stats = MovementStats {
+        max_speed: 100.,
+        acceleration: 10000.,
+        friction: 10000.,
+    };
+
+    let straight = stats.accelerate(Vec2::ZERO, Vec2::new(1., 0.), 1.);
+    let diagonal = stats.accelerate(Vec2::ZERO, Vec2::new(1., 1.), 1.);
+    assert!((straight.length() - 100.).abs() < 0.001);
+    assert!((diagonal.length() - 100.).abs() < 0.001);
+}
+
+#[test]
+fn should_accelerate_and_stop_by_friction_over_time() {
+    let stats = MovementStats {
+        max_speed: 100.,
+        acceleration: 200.,
+        friction: 400.,
+    };
+
+    let velocity = stats.accelerate(Vec2::ZERO, Vec2::X, 0.25);
+    assert_eq!(velocity, Vec2::new(50., 0.));
+    let velocity = stats.accelerate(velocity, Vec2::X, 0.5);
+    assert_eq!(velocity, Vec2::new(100., 0.));
+    let velocity = stats.accelerate(velocity, Vec2::ZERO, 0.125);
+    assert_eq!(velocity, Vec2::new(50., 0.));
+    let velocity = stats.accelerate(velocity, Vec2::ZERO, 1.);
+    assert_eq!(velocity, Vec2::ZERO);
 }
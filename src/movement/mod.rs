@@ -1,21 +1,34 @@
 use bevy::prelude::*;
 
-use crate::events::RuledEventQueue;
+use crate::{
+    events::RuledEventAppExt,
+    simulation::{SimulationAppExt, SimulationStage},
+};
 
 use self::{
-    components::{BlocksMovement, MoveAttempt},
-    systems::{move_entity, move_randomly},
+    components::{Impulse, IntegrateMovement, MoveAttempt, StopRejectedMoves},
+    systems::{
+        apply_impulses, integrate_velocity, move_entity, regain_control, stop_rejected_moves,
+    },
 };
 
 pub mod components;
+pub mod steering;
 mod systems;
 
 pub struct MovementPlugin;
 
 impl Plugin for MovementPlugin {
     fn build(&self, app: &mut App) {
-        app.insert_resource::<RuledEventQueue<MoveAttempt>>(RuledEventQueue::new())
-            .add_system(move_randomly.before(BlocksMovement))
-            .add_system(move_entity.after(BlocksMovement));
+        app.add_ruled_event::<MoveAttempt>()
+            .add_simulation_event::<Impulse>()
+            .add_simulation_system_to_stage(SimulationStage::First, apply_impulses)
+            .add_simulation_system(integrate_velocity.label(IntegrateMovement))
+            .add_event_consumer::<MoveAttempt, _>(move_entity)
+            .add_simulation_system_to_stage(
+                SimulationStage::PostUpdate,
+                stop_rejected_moves.label(StopRejectedMoves),
+            )
+            .add_simulation_system_to_stage(SimulationStage::PostUpdate, regain_control);
     }
 }
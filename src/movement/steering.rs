@@ -0,0 +1,168 @@
+//! Steering behaviours each return a desired movement intent. Intents have at most a length of 1
+//! which is the full speed of the unit, several of them are weighted and blended into one.
+
+use bevy::{ecs::system::SystemParam, math::Mat2, prelude::*};
+use rand::Rng;
+
+use super::components::Wander;
+use crate::{
+    collision::components::{
+        CollisionLayer, CollisionLayers, Hitbox, MovementColliders, SpatialHash,
+    },
+    map::components::{Map, RoomBound, Wading},
+};
+
+/// How far ahead units look for obstacles in pixels
+const LOOKAHEAD: f32 = 24.;
+/// Angle of the side feelers next to the heading
+const FEELER_ANGLE: f32 = std::f32::consts::FRAC_PI_4;
+/// Radians a wandering unit turns at most per second
+const WANDER_TURN_RATE: f32 = 3.;
+const SEPARATION_RADIUS: f32 = 40.;
+const AVOIDANCE_WEIGHT: f32 = 2.;
+const SEPARATION_WEIGHT: f32 = 1.;
+
+/// Full speed towards the target
+pub fn seek(position: Vec2, target: Vec2) -> Vec2 {
+    (target - position).normalize_or_zero()
+}
+
+/// Full speed away from the threat
+pub fn flee(position: Vec2, threat: Vec2) -> Vec2 {
+    -seek(position, threat)
+}
+
+/// Seeks the target but slows down within the radius to stop on it
+pub fn arrive(position: Vec2, target: Vec2, slowing_radius: f32) -> Vec2 {
+    let offset = target - position;
+    offset.normalize_or_zero() * (offset.length() / slowing_radius).min(1.)
+}
+
+/// Turns the wandering direction a bit at random every tick
+pub fn wander(wander: &mut Wander, delta_seconds: f32, rng: &mut impl Rng) -> Vec2 {
+    wander.angle += rng.gen_range(-1.0..=1.0) * WANDER_TURN_RATE * delta_seconds;
+    wander.direction()
+}
+
+/// Flees from every neighbour closer than the radius, the closer the stronger
+pub fn separate(position: Vec2, neighbours: impl Iterator<Item = Vec2>, radius: f32) -> Vec2 {
+    neighbours
+        .map(|neighbour| {
+            let closeness = 1. - position.distance(neighbour) / radius;
+            flee(position, neighbour) * closeness.max(0.)
+        })
+        .fold(Vec2::ZERO, |sum, intent| sum + intent)
+        .clamp_length_max(1.)
+}
+
+/// Probes ahead and to both sides of the heading and steers away from the sides that are blocked.
+/// `is_blocked` gets the offset of a probe from the unit.
+pub fn avoid_obstacles(heading: Vec2, is_blocked: impl Fn(Vec2) -> bool) -> Vec2 {
+    let heading = heading.normalize_or_zero();
+    if heading == Vec2::ZERO {
+        return Vec2::ZERO;
+    }
+    let probe = |angle: f32| is_blocked(Mat2::from_angle(angle) * heading * LOOKAHEAD);
+    let left = heading.perp();
+    let mut steering = Vec2::ZERO;
+    if probe(FEELER_ANGLE) {
+        steering -= left;
+    }
+    if probe(-FEELER_ANGLE) {
+        steering += left;
+    }
+    if probe(0.) {
+        // Brake and turn away, to the left if both sides look the same
+        steering -= heading;
+        if steering.dot(left) == 0. {
+            steering += left;
+        }
+    }
+    steering.clamp_length_max(1.)
+}
+
+/// Sums up the weighted intents of several behaviours
+pub fn blend(behaviours: &[(Vec2, f32)]) -> Vec2 {
+    behaviours
+        .iter()
+        .map(|(intent, weight)| *intent * *weight)
+        .fold(Vec2::ZERO, |sum, intent| sum + intent)
+        .clamp_length_max(1.)
+}
+
+/// What enemies steer around: walls, water they can't wade, the bounds of their room and each
+/// other
+#[derive(SystemParam)]
+pub struct Obstacles<'w, 's> {
+    map: Res<'w, Map>,
+    spatial_hash: Res<'w, SpatialHash>,
+    movement_colliders: MovementColliders<'w, 's>,
+    room_bound: Query<'w, 's, (), With<RoomBound>>,
+    wading: Query<'w, 's, &'static Wading>,
+}
+
+impl<'w, 's> Obstacles<'w, 's> {
+    /// Blends the intent of a unit with steering clear of walls and other units
+    pub fn steer(&self, unit: Entity, position: Vec3, intent: Vec2) -> Vec2 {
+        let feet = self.movement_colliders.hitbox_at(unit, position);
+        let center = feet.as_ref().map_or(position, |feet| feet.pos);
+        let room_bound = self.room_bound.get(unit).is_ok();
+        let wading = self.wading.get(unit).copied().unwrap_or_default();
+        let is_blocked = |offset: Vec2| {
+            let offset = offset.extend(0.);
+            let hits_wall = feet.as_ref().is_some_and(|feet| {
+                let probe = Hitbox {
+                    pos: feet.pos + offset,
+                    ..feet.clone()
+                };
+                self.map.blocking_tile(&probe, wading).is_some()
+            });
+            hits_wall || (room_bound && !self.map.within_room(position + offset))
+        };
+
+        let own_collider = self.movement_colliders.entity(unit);
+        let neighbours = self
+            .spatial_hash
+            .within_radius(
+                center,
+                SEPARATION_RADIUS,
+                &CollisionLayers::detecting(&[CollisionLayer::Enemy]),
+            )
+            .into_iter()
+            .filter(|(collider, _)| {
+                Some(*collider) != own_collider && self.movement_colliders.contains(*collider)
+            })
+            .map(|(_, hitbox)| hitbox.pos.truncate());
+
+        blend(&[
+            (intent, 1.),
+            (avoid_obstacles(intent, is_blocked), AVOIDANCE_WEIGHT),
+            (
+                separate(center.truncate(), neighbours, SEPARATION_RADIUS),
+                SEPARATION_WEIGHT,
+            ),
+        ])
+    }
+}
+
+#[test]
+fn should_slow_down_when_arriving() {
+    let far = arrive(Vec2::ZERO, Vec2::new(100., 0.), 20.);
+    let close = arrive(Vec2::ZERO, Vec2::new(5., 0.), 20.);
+    assert_eq!(far, Vec2::X);
+    assert_eq!(close, Vec2::new(0.25, 0.));
+    assert_eq!(arrive(Vec2::ZERO, Vec2::ZERO, 20.), Vec2::ZERO);
+}
+
+#[test]
+fn should_steer_away_from_blocked_side() {
+    // A wall to the upper right of a unit heading right
+    let steering = avoid_obstacles(Vec2::X, |offset| offset.y > 1.);
+    assert!(steering.y < 0.);
+
+    // A wall straight ahead turns the unit around to the left
+    let steering = avoid_obstacles(Vec2::X, |offset| offset.x > 20.);
+    assert!(steering.x < 0. && steering.y > 0.);
+
+    assert_eq!(avoid_obstacles(Vec2::X, |_| false), Vec2::ZERO);
+}
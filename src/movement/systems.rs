@@ -1,56 +1,250 @@
+use std::{collections::BTreeMap, time::Duration};
+
 use bevy::prelude::*;
-use rand::Rng;
 
-use super::components::{MoveAttempt, MovingRandomly};
-use crate::{events::RuledEventQueue, global_components::Direction};
+#[cfg(test)]
+use crate::map::{
+    components::{map_idx, map_with_floor, CorridorSettings, Rectangle, TileType},
+    systems::slow_on_terrain,
+};
+#[cfg(test)]
+use bevy::ecs::event::Events;
+#[cfg(test)]
+use rand::{rngs::StdRng, SeedableRng};
 
-const STEPS_IN_SAME_DIRECTION: i32 = 15;
+use super::components::{
+    Impulse, Knockback, Mass, MoveAttempt, MoveIntent, MovementStats, Velocity,
+};
+use crate::{
+    collision::components::MovementColliders,
+    events::RuledEventQueue,
+    map::components::Map,
+    simulation::{simulation_step, SIMULATION_STEP},
+};
 
 pub fn move_entity(
     mut move_events: ResMut<RuledEventQueue<MoveAttempt>>,
-    mut transforms: Query<&mut Transform>,
+    mut transforms: Query<(&mut Transform, Option<&mut Velocity>)>,
 ) {
-    for move_attempt in move_events.read_events() {
-        if let Ok(mut trans) = transforms.get_mut(move_attempt.entity) {
+    for move_attempt in move_events.consume_events() {
+        if let Ok((mut trans, velocity)) = transforms.get_mut(move_attempt.entity) {
             trans.translation = move_attempt.destination;
+            // Units lose their speed along the axes an obstacle cut short, but not by slow effects
+            if let Some(mut velocity) = velocity {
+                let step = move_attempt.step();
+                let slowed_step = move_attempt.slowed_step();
+                if step.x.abs() < slowed_step.x.abs() {
+                    velocity.x = 0.;
+                }
+                if step.y.abs() < slowed_step.y.abs() {
+                    velocity.y = 0.;
+                }
+            }
+        }
+    }
+}
+
+pub fn stop_rejected_moves(
+    move_events: Res<RuledEventQueue<MoveAttempt>>,
+    mut velocities: Query<&mut Velocity>,
+) {
+    for move_attempt in move_events.rejected_events() {
+        if let Ok(mut velocity) = velocities.get_mut(move_attempt.entity) {
+            velocity.0 = Vec2::ZERO;
         }
     }
 }
 
-pub fn move_randomly(
+/// Integrates the velocity over one simulation tick. The resulting step still has to pass the
+/// rules of the move attempt before it is applied.
+#[allow(clippy::type_complexity)]
+pub fn integrate_velocity(
     mut move_events: ResMut<RuledEventQueue<MoveAttempt>>,
-    time: Res<Time>,
-    mut random_move_query: Query<(Entity, &mut Transform, &mut MovingRandomly)>,
+    map: Res<Map>,
+    movement_colliders: MovementColliders,
+    mut query: Query<(
+        Entity,
+        &Transform,
+        &MoveIntent,
+        &MovementStats,
+        &mut Velocity,
+        Option<&Knockback>,
+    )>,
 ) {
-    for (entity, transform, mut moving_randomly) in random_move_query.iter_mut() {
-        moving_randomly.timer.tick(time.delta());
-        if moving_randomly.timer.just_finished() {
-            // let the unit walk into one direction for some time to feel more natural
-            if moving_randomly.step_counter > STEPS_IN_SAME_DIRECTION {
-                let mut rng = rand::thread_rng();
-                moving_randomly.current_direction = match rng.gen_range(0..4) {
-                    0 => Direction::Right,
-                    1 => Direction::Left,
-                    2 => Direction::Up,
-                    _ => Direction::Down,
-                };
-                moving_randomly.step_counter = 0;
-            }
+    let delta_seconds = SIMULATION_STEP as f32;
+    for (entity, transform, intent, stats, mut velocity, knockback) in query.iter_mut() {
+        // Knocked back units only slow down until they regain control
+        let intent = if knockback.is_some() {
+            Vec2::ZERO
+        } else {
+            intent.0
+        };
+        // Units feel the ground under their feet
+        let feet = movement_colliders
+            .hitbox_at(entity, transform.translation)
+            .map_or(transform.translation, |hitbox| hitbox.pos);
+        let stats = stats.on_terrain(map.terrain_at(feet));
+        velocity.0 = stats.accelerate(velocity.0, intent, delta_seconds);
+        let step = (velocity.0 * delta_seconds).extend(0.);
+        if step == Vec3::ZERO {
+            continue;
+        }
+        move_events.add_event(MoveAttempt::new(
+            entity,
+            transform.translation,
+            transform.translation + step,
+        ));
+    }
+}
 
-            let destination = match moving_randomly.current_direction {
-                Direction::Right => {
-                    transform.translation + Vec3::new(moving_randomly.speed, 0., 0.)
-                }
-                Direction::Left => transform.translation - Vec3::new(moving_randomly.speed, 0., 0.),
-                Direction::Up => transform.translation + Vec3::new(0., moving_randomly.speed, 0.),
-                Direction::Down => transform.translation - Vec3::new(0., moving_randomly.speed, 0.),
-            };
-            move_events.add_event(MoveAttempt::new(
-                entity,
-                destination,
-                moving_randomly.current_direction,
-            ));
-            moving_randomly.step_counter += 1;
+/// Runs before the tick integrates velocities so pushed units lose control right away
+pub fn apply_impulses(
+    mut commands: Commands,
+    mut impulses: EventReader<Impulse>,
+    mut units: Query<(&mut Velocity, Option<&Mass>, Option<&Knockback>)>,
+) {
+    // Several impulses in one tick add up and the longest loss of control wins
+    let mut control_losses: BTreeMap<Entity, f32> = BTreeMap::new();
+    for impulse in impulses.iter() {
+        if let Ok((mut velocity, mass, _)) = units.get_mut(impulse.entity) {
+            velocity.0 += mass
+                .copied()
+                .unwrap_or_default()
+                .velocity_change(impulse.impulse);
+            let control_loss = control_losses.entry(impulse.entity).or_default();
+            *control_loss = control_loss.max(impulse.control_loss);
+        }
+    }
+    for (entity, control_loss) in control_losses {
+        let remaining = match units.get(entity) {
+            Ok((_, _, Some(knockback))) => knockback.0.duration() - knockback.0.elapsed(),
+            _ => Duration::ZERO,
+        };
+        let duration = remaining.max(Duration::from_secs_f32(control_loss));
+        commands
+            .entity(entity)
+            .insert(Knockback(Timer::new(duration, false)));
+    }
+}
+
+pub fn regain_control(mut commands: Commands, mut query: Query<(Entity, &mut Knockback)>) {
+    for (entity, mut knockback) in query.iter_mut() {
+        knockback.0.tick(simulation_step());
+        if knockback.0.finished() {
+            commands.entity(entity).remove::<Knockback>();
         }
     }
 }
+
+#[test]
+fn should_apply_queued_move_exactly_once() {
+    let mut world = World::new();
+    world.insert_resource(RuledEventQueue::<MoveAttempt>::new());
+    let entity = world.spawn().insert(Transform::default()).id();
+    let mut update_stage = SystemStage::single_threaded().with_system(move_entity);
+    let mut last_stage =
+        SystemStage::single_threaded().with_system(RuledEventQueue::<MoveAttempt>::update_system);
+
+    world
+        .resource_mut::<RuledEventQueue<MoveAttempt>>()
+        .add_event(MoveAttempt::new(entity, Vec3::ZERO, Vec3::new(2., 0., 0.)));
+    update_stage.run(&mut world);
+    last_stage.run(&mut world);
+    assert_eq!(
+        world.get::<Transform>(entity).unwrap().translation,
+        Vec3::new(2., 0., 0.)
+    );
+
+    // Reset the position so a replayed move would show up
+    world.get_mut::<Transform>(entity).unwrap().translation = Vec3::ZERO;
+    for _ in 0..2 {
+        update_stage.run(&mut world);
+        last_stage.run(&mut world);
+    }
+    assert_eq!(
+        world.get::<Transform>(entity).unwrap().translation,
+        Vec3::ZERO
+    );
+}
+
+#[test]
+fn should_keep_speed_of_unit_slowed_by_mud() {
+    let mut world = World::new();
+    world.insert_resource(RuledEventQueue::<MoveAttempt>::new());
+    let mut map = map_with_floor(&Rectangle::new(2, 2, 8, 8));
+    map.tiles[map_idx(5, 5)] = TileType::Mud;
+    world.insert_resource(map);
+    let origin = Vec3::new(160., 160., 0.);
+    let unit = world
+        .spawn()
+        .insert(Transform::from_translation(origin))
+        .insert(Velocity(Vec2::new(60., 0.)))
+        .id();
+    let mut update_stage = SystemStage::single_threaded()
+        .with_system(slow_on_terrain)
+        .with_system(move_entity.after(slow_on_terrain));
+
+    world
+        .resource_mut::<RuledEventQueue<MoveAttempt>>()
+        .add_event(MoveAttempt::new(
+            unit,
+            origin,
+            origin + Vec3::new(1., 0., 0.),
+        ));
+    update_stage.run(&mut world);
+
+    let speed = TileType::Mud.terrain().speed;
+    assert_eq!(
+        world.get::<Transform>(unit).unwrap().translation,
+        origin + Vec3::new(speed, 0., 0.)
+    );
+    assert_eq!(world.get::<Velocity>(unit).unwrap().0, Vec2::new(60., 0.));
+}
+
+#[test]
+fn should_ignore_intent_while_knocked_back() {
+    let mut world = World::new();
+    world.insert_resource(RuledEventQueue::<MoveAttempt>::new());
+    world.init_resource::<Events<Impulse>>();
+    world.insert_resource(Map::new(
+        CorridorSettings::default(),
+        &mut StdRng::seed_from_u64(0),
+    ));
+    let stats = MovementStats {
+        max_speed: 100.,
+        acceleration: 1000.,
+        friction: 600.,
+    };
+    let unit = world
+        .spawn()
+        .insert(Transform::default())
+        .insert(MoveIntent(Vec2::X))
+        .insert(stats)
+        .insert(Velocity::default())
+        .insert(Mass(2.))
+        .id();
+    let mut first_stage = SystemStage::single_threaded().with_system(apply_impulses);
+    let mut update_stage = SystemStage::single_threaded()
+        .with_system(integrate_velocity)
+        .with_system(regain_control.after(integrate_velocity));
+    let mut run_tick = |world: &mut World| {
+        first_stage.run(world);
+        update_stage.run(world);
+    };
+
+    world.resource_mut::<Events<Impulse>>().send(Impulse {
+        entity: unit,
+        impulse: Vec2::new(-600., 0.),
+        control_loss: 0.1,
+    });
+    run_tick(&mut world);
+    // Half the impulse for a mass of 2, slowed down by one tick of friction
+    assert!((world.get::<Velocity>(unit).unwrap().x + 290.).abs() < 0.001);
+
+    for _ in 0..10 {
+        run_tick(&mut world);
+    }
+    // Friction alone would only have slowed the unit down to -190
+    assert!(world.get::<Knockback>(unit).is_none());
+    assert!(world.get::<Velocity>(unit).unwrap().x > -190.);
+}
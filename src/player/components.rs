@@ -1,11 +1,73 @@
-use crate::events::RuledEventQueue;
+use crate::collision::components::Collider;
 use crate::global_components::Direction;
-use crate::movement::components::MoveAttempt;
+use crate::map::components::Wading;
+use crate::movement::components::{MoveIntent, MovementStats};
+use bevy::math::const_vec2;
 use bevy::prelude::*;
 
 use bevy_asset_loader::AssetCollection;
+use std::time::Duration;
 
-const PLAYER_MOVEMENTSPEED: f32 = 2.0;
+pub const PLAYER_MOVEMENT: MovementStats = MovementStats {
+    max_speed: 120.,
+    acceleration: 1200.,
+    friction: 1200.,
+};
+
+/// Only the feet of the player are stopped by walls so the head can overlap the wall above
+pub const PLAYER_FEET: Collider = Collider {
+    offset: const_vec2!([0., -20.]),
+    size: Some(const_vec2!([28., 16.])),
+};
+
+/// The player wades through any water of the dungeon
+pub const PLAYER_WADING: Wading = Wading(28.);
+
+pub const DASH_KEY: KeyCode = KeyCode::Space;
+/// The dash is a knockback the player gives themself, so walls and units stop it like any push
+pub const DASH_IMPULSE: f32 = 450.;
+pub const DASH_DURATION: f32 = 0.25;
+const DASH_COOLDOWN: f32 = 0.8;
+pub const DASH_INVULNERABILITY: f32 = 0.3;
+
+/// Lets the player dodge in the direction of the input or where they are looking
+#[derive(Component)]
+pub struct Dash {
+    /// Set when the key was pressed in a frame, the next tick tries to dash
+    pub requested: bool,
+    cooldown: Timer,
+    /// Seconds the player can't be hit from the start of a dash
+    pub invulnerability: Option<f32>,
+}
+
+impl Dash {
+    /// Invulnerability is given in seconds, without it the player can be hit while dashing
+    pub fn new(invulnerability: Option<f32>) -> Self {
+        let finished_timer = |seconds: f32| {
+            let mut timer = Timer::from_seconds(seconds, false);
+            timer.tick(timer.duration());
+            timer
+        };
+        Self {
+            requested: false,
+            cooldown: finished_timer(DASH_COOLDOWN),
+            invulnerability,
+        }
+    }
+
+    pub fn tick(&mut self, delta: Duration) {
+        self.cooldown.tick(delta);
+    }
+
+    /// Starts the dash unless it is still cooling down
+    pub fn start(&mut self) -> bool {
+        if !self.cooldown.finished() {
+            return false;
+        }
+        self.cooldown.reset();
+        true
+    }
+}
 
 #[derive(AssetCollection)]
 pub struct PlayerAssets {
@@ -38,44 +100,47 @@ pub fn camera_follow(
 
 pub fn move_player(
     keyboard_input: Res<Input<KeyCode>>,
-    mut move_events: ResMut<RuledEventQueue<MoveAttempt>>,
     mut player_query: Query<(
-        Entity,
-        &Transform,
+        &mut MoveIntent,
         &mut TextureAtlasSprite,
         &mut Handle<TextureAtlas>,
         &Player,
     )>,
 ) {
-    for (entity, trans, mut sprite, mut handle, player) in player_query.iter_mut() {
+    for (mut intent, mut sprite, mut handle, player) in player_query.iter_mut() {
+        intent.0 = Vec2::ZERO;
         if !keyboard_input.any_pressed([KeyCode::Left, KeyCode::Right, KeyCode::Up, KeyCode::Down])
         {
             *handle = player.idle_atlas.clone();
             return;
         }
-        let mut destination = trans.translation;
         if keyboard_input.pressed(KeyCode::Left) {
-            destination -= Vec3::new(PLAYER_MOVEMENTSPEED, 0., 0.);
-            let direction = Direction::Left;
+            intent.0 += Direction::Left.to_vec2();
             sprite.flip_x = true;
-            move_events.add_event(MoveAttempt::new(entity, destination, direction));
         }
         if keyboard_input.pressed(KeyCode::Right) {
-            destination += Vec3::new(PLAYER_MOVEMENTSPEED, 0., 0.);
-            let direction = Direction::Right;
+            intent.0 += Direction::Right.to_vec2();
             sprite.flip_x = false;
-            move_events.add_event(MoveAttempt::new(entity, destination, direction));
         }
         if keyboard_input.pressed(KeyCode::Up) {
-            destination += Vec3::new(0., PLAYER_MOVEMENTSPEED, 0.);
-            let direction = Direction::Up;
-            move_events.add_event(MoveAttempt::new(entity, destination, direction));
+            intent.0 += Direction::Up.to_vec2();
         }
         if keyboard_input.pressed(KeyCode::Down) {
-            destination -= Vec3::new(0., PLAYER_MOVEMENTSPEED, 0.);
-            let direction = Direction::Down;
-            move_events.add_event(MoveAttempt::new(entity, destination, direction));
+            intent.0 += Direction::Down.to_vec2();
         }
         *handle = player.run_atlas.clone();
     }
 }
+
+#[test]
+fn should_dash_again_only_after_cooldown() {
+    let mut dash = Dash::new(Some(DASH_INVULNERABILITY));
+    assert!(dash.start());
+
+    let cooldown = Duration::from_secs_f32(DASH_COOLDOWN);
+    dash.tick(cooldown - Duration::from_millis(1));
+    assert!(!dash.start());
+
+    dash.tick(Duration::from_millis(1));
+    assert!(dash.start());
+}
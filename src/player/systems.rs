@@ -1,8 +1,16 @@
-use crate::{collision::components::Hitbox, map::components::RoomBound};
-use crate::combat::components::Health;
+use crate::collision::components::{
+    collider_bundle, Collider, CollisionLayer, CollisionLayers, MovementCollider,
+};
+use crate::combat::components::{Health, Invulnerable};
 use crate::global_components::Rectangular;
+use crate::item::components::Wallet;
 use crate::map::components::Map;
-use crate::player::components::Player;
+use crate::movement::components::{Impulse, MoveIntent, Velocity};
+use crate::player::components::{
+    Dash, Player, DASH_DURATION, DASH_IMPULSE, DASH_INVULNERABILITY, DASH_KEY, PLAYER_FEET,
+    PLAYER_MOVEMENT, PLAYER_WADING,
+};
+use crate::simulation::{simulation_step, Interpolated};
 use bevy::prelude::*;
 
 use super::components::{AnimationTimer, PlayerAssets};
@@ -69,11 +77,25 @@ pub fn spawn_player(
             run_atlas: run_atlas_handle,
         })
         .insert(Health::new(30))
+        .insert(MoveIntent::default())
+        .insert(Velocity::default())
+        .insert(Interpolated::new(pos))
+        .insert(PLAYER_MOVEMENT)
+        .insert(PLAYER_WADING)
         .insert(Rectangular(size))
-        .insert(Hitbox {
-            pos,
-            width: 32.,
-            height: 42.,
+        .insert(Wallet::default())
+        .insert(Dash::new(Some(DASH_INVULNERABILITY)))
+        .with_children(|children| {
+            let layers = CollisionLayers::new(&[CollisionLayer::Player], &[CollisionLayer::Enemy]);
+            children
+                .spawn_bundle(collider_bundle(PLAYER_FEET, layers, pos, size * 2.))
+                .insert(MovementCollider);
+            children.spawn_bundle(collider_bundle(
+                Collider::sprite_bounds(),
+                layers,
+                pos,
+                size * 2.,
+            ));
         });
 }
 
@@ -97,3 +119,53 @@ pub fn animate_run_player(
         }
     }
 }
+
+/// Key presses are only seen in frames, so they are kept for the next tick
+pub fn request_dash(keyboard_input: Res<Input<KeyCode>>, mut query: Query<&mut Dash>) {
+    if keyboard_input.just_pressed(DASH_KEY) {
+        for mut dash in query.iter_mut() {
+            dash.requested = true;
+        }
+    }
+}
+
+/// Dashing makes the player invulnerable for a moment without shortening any longer
+/// invulnerability from elsewhere
+pub fn dash(
+    mut commands: Commands,
+    mut impulses: EventWriter<Impulse>,
+    mut query: Query<(
+        Entity,
+        &mut Dash,
+        &MoveIntent,
+        &TextureAtlasSprite,
+        Option<&mut Invulnerable>,
+    )>,
+) {
+    for (entity, mut dash, intent, sprite, invulnerable) in query.iter_mut() {
+        dash.tick(simulation_step());
+        if dash.requested {
+            dash.requested = false;
+            if dash.start() {
+                let facing = if sprite.flip_x { -Vec2::X } else { Vec2::X };
+                let direction = if intent.0 == Vec2::ZERO {
+                    facing
+                } else {
+                    intent.0.normalize()
+                };
+                impulses.send(Impulse {
+                    entity,
+                    impulse: direction * DASH_IMPULSE,
+                    control_loss: DASH_DURATION,
+                });
+                match (dash.invulnerability, invulnerable) {
+                    (Some(seconds), Some(mut invulnerable)) => invulnerable.extend(seconds),
+                    (Some(seconds), None) => {
+                        commands.entity(entity).insert(Invulnerable::new(seconds));
+                    }
+                    (None, _) => {}
+                }
+            }
+        }
+    }
+}
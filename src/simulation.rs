@@ -0,0 +1,209 @@
+use std::time::Duration;
+
+use bevy::{
+    core::{FixedTimestep, FixedTimesteps},
+    ecs::{event::Events, schedule::IntoSystemDescriptor, system::Resource},
+    prelude::*,
+    transform::TransformSystem,
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+/// Length of one simulation tick in seconds
+pub const SIMULATION_STEP: f64 = 1. / 60.;
+const SIMULATION_TIMESTEP: &str = "simulation";
+const SEED_VARIABLE: &str = "DUNGEON_SEED";
+
+/// Movement, collision and everything else that changes the state of the game runs in this nested
+/// schedule at a fixed rate after `CoreStage::Update`, so a frame may run zero or several ticks.
+#[derive(Clone, Debug, Hash, PartialEq, Eq, StageLabel)]
+pub struct SimulationSchedule;
+
+/// The stages of one simulation tick. Ruled events are validated and consumed between `Update`
+/// and `PostUpdate`. All stages run their systems single threaded in the order they were added
+/// so ticks are deterministic.
+#[derive(Clone, Debug, Hash, PartialEq, Eq, StageLabel)]
+pub enum SimulationStage {
+    First,
+    Update,
+    PostUpdate,
+    Last,
+}
+
+/// The seed of all randomness that affects the game. Set the `DUNGEON_SEED` environment variable
+/// to replay a run.
+pub struct SimulationSeed(pub u64);
+
+impl SimulationSeed {
+    pub fn from_env() -> Self {
+        let seed = std::env::var(SEED_VARIABLE)
+            .ok()
+            .and_then(|seed| seed.parse().ok())
+            .unwrap_or_else(|| rand::thread_rng().gen());
+        Self(seed)
+    }
+}
+
+/// Generating and simulating the dungeon has to draw every random number from here.
+/// Purely cosmetic randomness may still use the thread rng.
+#[derive(Deref, DerefMut)]
+pub struct SimulationRng(pub StdRng);
+
+impl SimulationRng {
+    /// Seeds the rng from the [`SimulationSeed`] when the first plugin needs it, so plugins that
+    /// draw from it while they are built don't depend on the order they are added in
+    pub fn get_or_seed(world: &mut World) -> Mut<'_, SimulationRng> {
+        let seed = world
+            .get_resource_or_insert_with(SimulationSeed::from_env)
+            .0;
+        world.get_resource_or_insert_with(|| SimulationRng(StdRng::seed_from_u64(seed)))
+    }
+}
+
+/// Units are rendered between their positions of the last two ticks, the simulation itself only
+/// ever sees the position of the last tick
+#[derive(Component)]
+pub struct Interpolated {
+    previous: Vec3,
+    current: Vec3,
+}
+
+impl Interpolated {
+    pub fn new(pos: Vec3) -> Self {
+        Self {
+            previous: pos,
+            current: pos,
+        }
+    }
+}
+
+pub struct SimulationPlugin;
+
+impl Plugin for SimulationPlugin {
+    fn build(&self, app: &mut App) {
+        let seed = app
+            .world
+            .get_resource_or_insert_with(SimulationSeed::from_env)
+            .0;
+        info!("Simulation seed: {}", seed);
+        SimulationRng::get_or_seed(&mut app.world);
+        app.add_simulation_system_to_stage(SimulationStage::First, restore_simulated_translation)
+            .add_simulation_system_to_stage(SimulationStage::Last, store_simulated_translation)
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                interpolate_translation.before(TransformSystem::TransformPropagate),
+            );
+    }
+}
+
+pub trait SimulationAppExt {
+    /// Adds a system that runs once every simulation tick
+    fn add_simulation_system<Params>(
+        &mut self,
+        system: impl IntoSystemDescriptor<Params>,
+    ) -> &mut Self;
+
+    fn add_simulation_system_to_stage<Params>(
+        &mut self,
+        stage: SimulationStage,
+        system: impl IntoSystemDescriptor<Params>,
+    ) -> &mut Self;
+
+    /// Like `add_event` but the events are cleared every tick instead of every frame, so readers
+    /// in the simulation can't miss them in frames without a tick
+    fn add_simulation_event<T: Resource>(&mut self) -> &mut Self;
+}
+
+impl SimulationAppExt for App {
+    fn add_simulation_system<Params>(
+        &mut self,
+        system: impl IntoSystemDescriptor<Params>,
+    ) -> &mut Self {
+        self.add_simulation_system_to_stage(SimulationStage::Update, system)
+    }
+
+    fn add_simulation_system_to_stage<Params>(
+        &mut self,
+        stage: SimulationStage,
+        system: impl IntoSystemDescriptor<Params>,
+    ) -> &mut Self {
+        simulation_schedule(self).add_system_to_stage(stage, system);
+        self
+    }
+
+    fn add_simulation_event<T: Resource>(&mut self) -> &mut Self {
+        if self.world.contains_resource::<Events<T>>() {
+            return self;
+        }
+        self.init_resource::<Events<T>>()
+            .add_simulation_system_to_stage(SimulationStage::Last, Events::<T>::update_system)
+    }
+}
+
+/// The nested schedule of the simulation, created on first use
+pub fn simulation_schedule(app: &mut App) -> &mut Schedule {
+    if app
+        .schedule
+        .get_stage::<Schedule>(&SimulationSchedule)
+        .is_none()
+    {
+        let schedule = Schedule::default()
+            .with_run_criteria(FixedTimestep::step(SIMULATION_STEP).with_label(SIMULATION_TIMESTEP))
+            .with_stage(SimulationStage::First, SystemStage::single_threaded())
+            .with_stage(SimulationStage::Update, SystemStage::single_threaded())
+            .with_stage(SimulationStage::PostUpdate, SystemStage::single_threaded())
+            .with_stage(SimulationStage::Last, SystemStage::single_threaded());
+        app.add_stage_after(CoreStage::Update, SimulationSchedule, schedule);
+    }
+    app.schedule
+        .get_stage_mut::<Schedule>(&SimulationSchedule)
+        .unwrap()
+}
+
+/// The duration of one tick for timers of the simulation
+pub fn simulation_step() -> Duration {
+    Duration::from_secs_f64(SIMULATION_STEP)
+}
+
+/// Takes back the interpolated translation the previous frame rendered
+fn restore_simulated_translation(mut query: Query<(&mut Transform, &mut Interpolated)>) {
+    for (mut transform, mut interpolated) in query.iter_mut() {
+        transform.translation = interpolated.current;
+        interpolated.previous = interpolated.current;
+    }
+}
+
+fn store_simulated_translation(mut query: Query<(&Transform, &mut Interpolated)>) {
+    for (transform, mut interpolated) in query.iter_mut() {
+        interpolated.current = transform.translation;
+    }
+}
+
+/// Blends between the last two ticks by how far the frame got into the next tick
+fn interpolate_translation(
+    fixed_timesteps: Res<FixedTimesteps>,
+    mut query: Query<(&mut Transform, &Interpolated)>,
+) {
+    let overstep = match fixed_timesteps.get(SIMULATION_TIMESTEP) {
+        Some(timestep) => timestep.overstep_percentage() as f32,
+        None => return,
+    };
+    for (mut transform, interpolated) in query.iter_mut() {
+        transform.translation = interpolated.previous.lerp(interpolated.current, overstep);
+    }
+}
+
+#[test]
+fn should_keep_drawing_from_rng_seeded_before_simulation_plugin() {
+    let mut app = App::new();
+    app.insert_resource(SimulationSeed(7));
+    let first: u64 = SimulationRng::get_or_seed(&mut app.world).gen();
+
+    app.add_plugin(SimulationPlugin);
+
+    let mut expected = StdRng::seed_from_u64(7);
+    assert_eq!(first, expected.gen::<u64>());
+    assert_eq!(
+        app.world.resource_mut::<SimulationRng>().gen::<u64>(),
+        expected.gen::<u64>()
+    );
+}
@@ -0,0 +1,163 @@
+use bevy::{
+    asset::{AssetLoader, BoxedFuture, LoadContext, LoadedAsset},
+    prelude::*,
+    reflect::TypeUuid,
+};
+use bevy_asset_loader::AssetCollection;
+use rand::{distributions::WeightedIndex, prelude::Distribution, Rng};
+use serde::Deserialize;
+
+use crate::{enemy::components::EnemyKind, item::components::ItemKind, map::components::RoomType};
+
+#[derive(AssetCollection)]
+pub struct SpawnAssets {
+    #[asset(path = "spawn_tables/dungeon.spawns.ron")]
+    pub spawn_table: Handle<SpawnTable>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub enum SpawnKind {
+    Enemy(EnemyKind),
+    Item(ItemKind),
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct SpawnEntry {
+    pub kind: SpawnKind,
+    pub weight: u32,
+    /// The entry is only rolled from this dungeon depth onwards
+    #[serde(default)]
+    pub min_depth: u32,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct RoomSpawns {
+    pub room_types: Vec<RoomType>,
+    /// Inclusive range of how many entities are rolled for a room
+    pub count: (u32, u32),
+    /// Raises the maximum count for every level below the first one
+    #[serde(default)]
+    pub extra_per_depth: u32,
+    pub entries: Vec<SpawnEntry>,
+}
+
+/// Weighted spawn tables loaded from a `.spawns.ron` file.
+/// Every table listing the type of a room is rolled for it, so a boss room can get its boss from
+/// one table and its minions from another.
+#[derive(Clone, Debug, Deserialize, TypeUuid)]
+#[uuid = "7c1ad4e4-0b7e-4b3e-9d0f-5a3b1f6f2c71"]
+pub struct SpawnTable {
+    pub rooms: Vec<RoomSpawns>,
+}
+
+impl SpawnTable {
+    pub fn roll(&self, room_type: RoomType, depth: u32, rng: &mut impl Rng) -> Vec<SpawnKind> {
+        let mut spawns = Vec::new();
+        for room_spawns in self
+            .rooms
+            .iter()
+            .filter(|r| r.room_types.contains(&room_type))
+        {
+            let entries: Vec<&SpawnEntry> = room_spawns
+                .entries
+                .iter()
+                .filter(|e| e.min_depth <= depth && e.weight > 0)
+                .collect();
+            if entries.is_empty() {
+                continue;
+            }
+            let weights = WeightedIndex::new(entries.iter().map(|e| e.weight)).unwrap();
+            let (min_count, max_count) = room_spawns.count;
+            let max_count = max_count + room_spawns.extra_per_depth * depth.saturating_sub(1);
+            let count = rng.gen_range(min_count..=max_count.max(min_count));
+            for _ in 0..count {
+                spawns.push(entries[weights.sample(rng)].kind);
+            }
+        }
+        spawns
+    }
+}
+
+#[derive(Default)]
+pub struct SpawnTableLoader;
+
+impl AssetLoader for SpawnTableLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let spawn_table: SpawnTable = ron::de::from_bytes(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(spawn_table));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["spawns.ron"]
+    }
+}
+
+#[cfg(test)]
+fn test_spawn_table() -> SpawnTable {
+    ron::de::from_str(
+        "(rooms: [
+            (
+                room_types: [Normal],
+                count: (2, 2),
+                extra_per_depth: 1,
+                entries: [
+                    (kind: Enemy(BigZombie), weight: 1),
+                    (kind: Enemy(BigDemon), weight: 1, min_depth: 3),
+                ],
+            ),
+            (room_types: [Boss, Normal], count: (1, 1), entries: [(kind: Item(Coin), weight: 1)]),
+        ])",
+    )
+    .unwrap()
+}
+
+#[test]
+fn should_roll_every_table_of_the_room_type() {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    let spawn_table = test_spawn_table();
+    let mut rng = StdRng::seed_from_u64(7);
+
+    for _ in 0..20 {
+        let spawns = spawn_table.roll(RoomType::Normal, 1, &mut rng);
+        let count = |kind: SpawnKind| spawns.iter().filter(|s| **s == kind).count();
+        assert_eq!(count(SpawnKind::Enemy(EnemyKind::BigZombie)), 2);
+        assert_eq!(count(SpawnKind::Item(ItemKind::Coin)), 1);
+        assert_eq!(spawns.len(), 3);
+
+        assert_eq!(
+            spawn_table.roll(RoomType::Boss, 1, &mut rng),
+            vec![SpawnKind::Item(ItemKind::Coin)]
+        );
+    }
+    assert!(spawn_table.roll(RoomType::Start, 1, &mut rng).is_empty());
+}
+
+#[test]
+fn should_unlock_entries_and_counts_with_depth() {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    let spawn_table = test_spawn_table();
+    let mut rng = StdRng::seed_from_u64(7);
+
+    for _ in 0..20 {
+        let spawns = spawn_table.roll(RoomType::Normal, 3, &mut rng);
+        let enemies = spawns
+            .iter()
+            .filter(|s| matches!(s, SpawnKind::Enemy(_)))
+            .count();
+        assert!((2..=4).contains(&enemies));
+    }
+    let demons_at_depth_one = (0..20)
+        .flat_map(|_| spawn_table.roll(RoomType::Normal, 1, &mut rng))
+        .filter(|s| *s == SpawnKind::Enemy(EnemyKind::BigDemon))
+        .count();
+    assert_eq!(demons_at_depth_one, 0);
+}
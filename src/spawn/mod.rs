@@ -0,0 +1,23 @@
+pub mod components;
+pub mod systems;
+
+use bevy::prelude::*;
+
+use crate::GameState;
+
+use self::{
+    components::{SpawnTable, SpawnTableLoader},
+    systems::spawn_room_contents,
+};
+
+pub struct SpawnPlugin;
+
+impl Plugin for SpawnPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<SpawnTable>()
+            .init_asset_loader::<SpawnTableLoader>()
+            .add_system_set(
+                SystemSet::on_enter(GameState::MapDrawn).with_system(spawn_room_contents),
+            );
+    }
+}
@@ -0,0 +1,95 @@
+use bevy::prelude::*;
+
+use crate::{
+    collision::components::Hitbox,
+    enemy::{components::EnemyAtlases, systems::spawn_enemy},
+    item::{components::ItemAssets, systems::spawn_item},
+    map::components::{DungeonDepth, Map, Wading},
+    simulation::SimulationRng,
+    TILE_SIZE,
+};
+
+use super::components::{SpawnAssets, SpawnKind, SpawnTable};
+
+const MAX_PLACEMENT_ATTEMPTS: usize = 20;
+const SPAWN_Z: f32 = 0.4;
+
+#[allow(clippy::too_many_arguments)]
+pub fn spawn_room_contents(
+    mut commands: Commands,
+    map: Res<Map>,
+    depth: Res<DungeonDepth>,
+    spawn_assets: Res<SpawnAssets>,
+    spawn_tables: Res<Assets<SpawnTable>>,
+    enemy_atlases: Res<EnemyAtlases>,
+    item_assets: Res<ItemAssets>,
+    hitboxes: Query<&Hitbox>,
+    mut rng: ResMut<SimulationRng>,
+) {
+    let spawn_table = spawn_tables
+        .get(&spawn_assets.spawn_table)
+        .expect("Spawn table not loaded?!");
+
+    // The player is spawned in the same frame so its hitbox can't be queried yet
+    let mut occupied: Vec<Hitbox> = hitboxes.iter().cloned().collect();
+    occupied.push(Hitbox {
+        pos: map.player_start_pos.extend(0.),
+        width: TILE_SIZE as f32 * 2.,
+        height: TILE_SIZE as f32 * 2.,
+    });
+
+    for room in map.rooms.iter() {
+        // The first enemy of every room patrols along its corners
+        let mut patrol_route = Some(
+            room.bounds
+                .inner_corners()
+                .iter()
+                .map(|(x, y)| {
+                    let tile = Vec2::new(*x as f32, *y as f32) * TILE_SIZE as f32;
+                    tile.extend(SPAWN_Z)
+                })
+                .collect::<Vec<Vec3>>(),
+        );
+        for kind in spawn_table.roll(room.room_type, depth.0, &mut rng.0) {
+            let (size, wading) = match kind {
+                SpawnKind::Enemy(enemy_kind) => (enemy_kind.hitbox_size(), enemy_kind.wading()),
+                SpawnKind::Item(item_kind) => (item_kind.size(), Wading::default()),
+            };
+            let pos = (0..MAX_PLACEMENT_ATTEMPTS).find_map(|_| {
+                let (x, y) = room.bounds.random_tile(&mut rng.0);
+                let candidate = Hitbox {
+                    pos: Vec3::new(
+                        (x * TILE_SIZE as i32) as f32,
+                        (y * TILE_SIZE as i32) as f32,
+                        SPAWN_Z,
+                    ),
+                    width: size.x,
+                    height: size.y,
+                };
+                let free = map.blocking_tile(&candidate, wading).is_none()
+                    && !occupied.iter().any(|o| candidate.collides_with(o));
+                free.then_some(candidate)
+            });
+            // Crowded rooms simply get fewer spawns
+            let hitbox = match pos {
+                Some(hitbox) => hitbox,
+                None => continue,
+            };
+
+            match kind {
+                SpawnKind::Enemy(enemy_kind) => spawn_enemy(
+                    &mut commands,
+                    &enemy_atlases,
+                    enemy_kind,
+                    hitbox.pos,
+                    room.difficulty,
+                    patrol_route.take(),
+                ),
+                SpawnKind::Item(item_kind) => {
+                    spawn_item(&mut commands, &item_assets, item_kind, hitbox.pos)
+                }
+            }
+            occupied.push(hitbox);
+        }
+    }
+}